@@ -0,0 +1,700 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+
+use crate::discourse::{Credentials, Secret};
+use crate::http::RateLimit;
+use crate::llamacpp::Endpoint;
+use crate::ollama::{ModelInfo, Options};
+use crate::prompt::{
+    PROMPT_TEMPLATE, PromptKind, REDUCE_TEMPLATE, STRUCTURED_SYSTEM_PROMPT, SYSTEM_PROMPT,
+};
+use crate::summarizer::count_tokens;
+use crate::{Forum, parse_forums};
+
+/// Config file read when neither `--config` nor `DIGEST_CONFIG` is given.
+pub const DEFAULT_CONFIG_PATH: &str = "digest.toml";
+
+/// Fewer tokens of thread text per prompt than this is a misconfiguration.
+const MIN_CHUNK_TOKENS: usize = 128;
+
+/// Chunks per reduce level assumed by the default `summarize.token_budget`.
+pub const REDUCE_FAN_IN: usize = 4;
+
+/// Typed pipeline configuration, loaded from TOML with env-var overrides.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub digest: DigestConfig,
+    pub fetch: FetchConfig,
+    pub summarize: SummarizeConfig,
+    pub ollama: OllamaConfig,
+    pub openai: OpenAiConfig,
+    pub llamacpp: LlamaCppConfig,
+    pub forums: Vec<Forum>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DigestConfig {
+    /// Only posts newer than this many hours are summarized.
+    pub cutoff_hours: i64,
+    /// When set, write one merged digest with a section per forum here.
+    pub merge_dir: Option<PathBuf>,
+    /// Title prefix of the merged digest.
+    pub merged_title: String,
+    /// Directory holding the SQLite store (`digest.sqlite`) and the report of
+    /// the last run (`run-report.json`).
+    pub data_dir: PathBuf,
+    /// Keep topics whose summary failed in the digest, with a notice, instead
+    /// of leaving them out.
+    pub render_failures: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Posts requested per `/t/{id}/posts.json` call during incremental sync.
+    pub page_size: usize,
+    /// Upper bound on `/latest.json` pages followed via `more_topics_url`.
+    pub max_latest_pages: usize,
+    /// Sustained request rate per forum host.
+    pub requests_per_sec: f64,
+    /// Requests that may be sent back to back before the rate applies.
+    pub burst: u32,
+    /// Hard cap on requests per forum host in one run.
+    pub max_requests_per_host: usize,
+    /// Max retry duration for a single forum request.
+    pub max_elapsed_secs: u64,
+    /// Longest `Retry-After` delay honoured; at most `max_elapsed_secs`.
+    pub max_retry_after_secs: u64,
+    /// Topics synced at the same time.
+    pub concurrency: usize,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SummarizeConfig {
+    /// LLM backend that writes the summaries.
+    pub backend: Backend,
+    /// Request structured JSON summaries (headline, bullets, decisions, open
+    /// questions, action items) instead of free text.
+    pub structured: bool,
+    /// Replaces the built-in system prompt.
+    pub system_prompt: Option<String>,
+    /// Send no system message and rely on the model's own prompt, e.g. the
+    /// `Modelfile` SYSTEM block.
+    pub use_model_system_prompt: bool,
+    /// Context window of the model in tokens; `ollama.options.num_ctx` takes
+    /// precedence with the Ollama backend.
+    pub context_tokens: usize,
+    /// Tokens of the context window kept free for the reply.
+    pub reply_tokens: usize,
+    /// Caps the chunk size derived from the context window.
+    pub chunk_max_tokens: Option<usize>,
+    /// Newest posts per topic that are chunked; older ones are left out.
+    pub max_posts_for_chunk: usize,
+    /// Max tokens of thread text summarized per topic; the oldest chunks
+    /// after the first are dropped. Defaults to what `max_depth` reduce levels of
+    /// [`REDUCE_FAN_IN`] chunks each can take in.
+    pub token_budget: Option<usize>,
+    /// Reduce levels for threads longer than one chunk; the last level
+    /// combines everything left in one call.
+    pub max_depth: usize,
+    /// Timeout of a single LLM call.
+    pub timeout_secs: u64,
+    /// LLM requests in flight at the same time.
+    pub concurrency: usize,
+}
+
+/// Summarization backends selectable with `summarize.backend`.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Ollama's native `/api/chat` (`[ollama]`).
+    #[default]
+    Ollama,
+    /// Any OpenAI-compatible `/v1/chat/completions` server (`[openai]`).
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// llama.cpp's `llama-server` (`[llamacpp]`).
+    LlamaCpp,
+    /// Deterministic offline summaries, for tests and dry runs.
+    Mock,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OllamaConfig {
+    pub base_url: String,
+    pub model: String,
+    /// Max retry duration for a single Ollama call.
+    pub max_elapsed_secs: u64,
+    /// Generation options sent with every request.
+    pub options: Options,
+    /// Per prompt kind overrides of `options`, e.g. `[ollama.prompt_options.warmup]`.
+    pub prompt_options: HashMap<PromptKind, Options>,
+    /// Stream replies: progress is logged while the model generates, and a
+    /// reply cut off by `summarize.timeout_secs` is kept as truncated.
+    pub stream: bool,
+    /// Stop reading a streamed reply after this many tokens.
+    pub max_output_tokens: Option<usize>,
+    /// Install the model when it is missing instead of failing.
+    pub bootstrap: bool,
+    /// Modelfile the model is created from by `bootstrap`; when absent, or
+    /// when its `FROM` is the model itself, the model is pulled instead.
+    pub modelfile: PathBuf,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    /// Server root; `/v1/chat/completions` is appended.
+    pub base_url: String,
+    pub model: String,
+    /// Bearer token, for servers that require one.
+    pub api_key: Option<String>,
+    /// Max retry duration for a single call.
+    pub max_elapsed_secs: u64,
+}
+
+/// Which topics of a forum make it into the digest.
+///
+/// With any allow list set, a topic must match one of them: its id, its
+/// category (or the category's parent) or one of its tags. The deny lists
+/// win over the allow lists.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForumFilter {
+    /// Category slugs to include, subcategories included.
+    pub categories: Vec<String>,
+    pub exclude_categories: Vec<String>,
+    pub tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    pub topics: Vec<u64>,
+    pub exclude_topics: Vec<u64>,
+    /// Read the listings of `categories` and `tags` instead of
+    /// `/latest.json`.
+    pub use_listings: bool,
+}
+
+impl ForumFilter {
+    /// Whether topic `id`, in the category with slug path `category` (see
+    /// [`category_path`](crate::discourse::category_path)) and tagged `tags`,
+    /// passes the filter.
+    pub fn allows(&self, id: u64, category: Option<&str>, tags: &[String]) -> bool {
+        let in_category = |slugs: &[String]| {
+            category.is_some_and(|path| path.split('/').any(|s| slugs.iter().any(|c| c == s)))
+        };
+        let tagged = |names: &[String]| tags.iter().any(|t| names.contains(t));
+        if self.exclude_topics.contains(&id)
+            || in_category(&self.exclude_categories)
+            || tagged(&self.exclude_tags)
+        {
+            return false;
+        }
+        let no_allow_lists =
+            self.topics.is_empty() && self.categories.is_empty() && self.tags.is_empty();
+        no_allow_lists
+            || self.topics.contains(&id)
+            || in_category(&self.categories)
+            || tagged(&self.tags)
+    }
+}
+
+/// Discourse credentials of one forum, set directly or read from
+/// `secrets_file` so the keys can stay out of the checked-in config.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForumAuth {
+    /// Admin or granular API key, sent as `Api-Key`.
+    pub api_key: Option<Secret>,
+    /// User the API key acts as, sent as `Api-Username`.
+    pub api_username: Option<String>,
+    /// User API key, sent as `User-Api-Key`.
+    pub user_api_key: Option<Secret>,
+    /// TOML file with any of the keys above. Values set in the config win.
+    pub secrets_file: Option<PathBuf>,
+}
+
+impl ForumAuth {
+    /// Resolve the credentials to send, reading `secrets_file` if set.
+    pub fn credentials(&self) -> Result<Credentials> {
+        let mut auth = self.clone();
+        if let Some(path) = &self.secrets_file {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("reading secrets file {}", path.display()))?;
+            // Only the message: the error's source snippet may quote a key.
+            let file: ForumAuth = toml::from_str(&text)
+                .map_err(|e| anyhow!("parsing secrets file {}: {}", path.display(), e.message()))?;
+            if file.secrets_file.is_some() {
+                bail!(
+                    "secrets file {} must not set `secrets_file`",
+                    path.display()
+                );
+            }
+            auth.api_key = auth.api_key.or(file.api_key);
+            auth.api_username = auth.api_username.or(file.api_username);
+            auth.user_api_key = auth.user_api_key.or(file.user_api_key);
+        }
+        match (auth.api_key, auth.user_api_key) {
+            (Some(_), Some(_)) => bail!("set only one of `api_key` and `user_api_key`"),
+            (Some(key), None) => {
+                let Some(username) = auth.api_username.filter(|u| !u.is_empty()) else {
+                    bail!("`api_key` needs `api_username`");
+                };
+                Ok(Credentials::ApiKey { key, username })
+            }
+            (None, Some(key)) => Ok(Credentials::UserApiKey { key }),
+            (None, None) => bail!("one of `api_key` and `user_api_key` must be set"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlamaCppConfig {
+    pub base_url: String,
+    /// Label recorded with runs and cache keys.
+    pub model: String,
+    /// `chat` (`/v1/chat/completions`) or `completion` (`/completion`).
+    pub endpoint: Endpoint,
+    /// Max tokens to generate; -1 generates until end of stream.
+    pub n_predict: i32,
+    /// GBNF grammar constraining the output.
+    pub grammar: Option<String>,
+    /// JSON schema constraining the output.
+    pub json_schema: Option<serde_json::Value>,
+    /// Max retry duration for a single call.
+    pub max_elapsed_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            digest: DigestConfig::default(),
+            fetch: FetchConfig::default(),
+            summarize: SummarizeConfig::default(),
+            ollama: OllamaConfig::default(),
+            openai: OpenAiConfig::default(),
+            llamacpp: LlamaCppConfig::default(),
+            forums: vec![Forum::default()],
+        }
+    }
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        DigestConfig {
+            cutoff_hours: 24,
+            merge_dir: None,
+            merged_title: "Forum Digest".to_string(),
+            data_dir: PathBuf::from("data"),
+            render_failures: false,
+        }
+    }
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            page_size: 20,
+            max_latest_pages: 10,
+            requests_per_sec: 1.0,
+            burst: 3,
+            max_requests_per_host: 2_000,
+            max_elapsed_secs: 60,
+            max_retry_after_secs: 30,
+            concurrency: 4,
+        }
+    }
+}
+
+impl Default for SummarizeConfig {
+    fn default() -> Self {
+        SummarizeConfig {
+            backend: Backend::Ollama,
+            structured: true,
+            system_prompt: None,
+            use_model_system_prompt: false,
+            context_tokens: 8_192,
+            reply_tokens: 1_024,
+            chunk_max_tokens: None,
+            max_posts_for_chunk: 200,
+            token_budget: None,
+            max_depth: 2,
+            timeout_secs: 240,
+            concurrency: 1,
+        }
+    }
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        OllamaConfig {
+            base_url: "http://127.0.0.1:11434".to_string(),
+            model: "qwen2.5:latest".to_string(),
+            max_elapsed_secs: 120,
+            options: Options::default(),
+            prompt_options: HashMap::new(),
+            stream: true,
+            max_output_tokens: None,
+            bootstrap: false,
+            modelfile: PathBuf::from("Modelfile"),
+        }
+    }
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        OpenAiConfig {
+            base_url: "http://127.0.0.1:8080".to_string(),
+            model: "default".to_string(),
+            api_key: None,
+            max_elapsed_secs: 120,
+        }
+    }
+}
+
+impl Default for LlamaCppConfig {
+    fn default() -> Self {
+        LlamaCppConfig {
+            base_url: "http://127.0.0.1:8080".to_string(),
+            model: "llama-server".to_string(),
+            endpoint: Endpoint::Chat,
+            n_predict: 512,
+            grammar: None,
+            json_schema: None,
+            max_elapsed_secs: 120,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration for this run.
+    ///
+    /// `path` takes precedence over `DIGEST_CONFIG`; if neither is given,
+    /// `digest.toml` is read when present and built-in defaults are used
+    /// otherwise. Env-var overrides are applied last, then the result is
+    /// validated.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let env_path = std::env::var_os("DIGEST_CONFIG").map(PathBuf::from);
+        let mut config = match path.map(Path::to_path_buf).or(env_path) {
+            Some(p) => Config::from_file(&p)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Config::default(),
+        };
+        config.apply_overrides(|key| std::env::var(key).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        Config::from_toml(&text).with_context(|| format!("parsing config {}", path.display()))
+    }
+
+    /// Parse a TOML document. Unknown keys are rejected.
+    pub fn from_toml(text: &str) -> Result<Config> {
+        let mut config: Config = toml::from_str(text)?;
+        for forum in &mut config.forums {
+            if forum.output_dir.as_os_str().is_empty() {
+                forum.output_dir = Forum::default_output_dir(&forum.name);
+            }
+            forum.base_url = forum.base_url.trim_end_matches('/').to_string();
+        }
+        Ok(config)
+    }
+
+    /// Apply env-var overrides, looking each variable up through `var`.
+    pub fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(v) = var("LLM_BACKEND") {
+            self.summarize.backend = match v.as_str() {
+                "ollama" => Backend::Ollama,
+                "openai" => Backend::OpenAi,
+                "llamacpp" => Backend::LlamaCpp,
+                "mock" => Backend::Mock,
+                _ => bail!("LLM_BACKEND: invalid value {v:?}"),
+            };
+        }
+        if let Some(v) = var("LLM_MODEL") {
+            self.set_model(v);
+        }
+        if let Some(v) = var("OLLAMA_BASE_URL") {
+            self.ollama.base_url = v;
+        }
+        if let Some(v) = var("OPENAI_BASE_URL") {
+            self.openai.base_url = v;
+        }
+        if let Some(v) = var("LLAMACPP_BASE_URL") {
+            self.llamacpp.base_url = v;
+        }
+        if let Some(v) = var("OPENAI_API_KEY") {
+            self.openai.api_key = Some(v);
+        }
+        if let Some(v) = var("OLLAMA_MAX_ELAPSED_SECS") {
+            self.ollama.max_elapsed_secs = v
+                .parse()
+                .with_context(|| format!("OLLAMA_MAX_ELAPSED_SECS: invalid value {v:?}"))?;
+        }
+        if let Some(v) = var("DIGEST_CUTOFF_HOURS") {
+            self.digest.cutoff_hours = v
+                .parse()
+                .with_context(|| format!("DIGEST_CUTOFF_HOURS: invalid value {v:?}"))?;
+        }
+        if let Some(v) = var("DIGEST_FORUMS") {
+            self.forums = parse_forums(&v).context("DIGEST_FORUMS")?;
+        }
+        if let Some(v) = var("DIGEST_MERGE_DIR") {
+            self.digest.merge_dir = Some(PathBuf::from(v));
+        }
+        if let Some(v) = var("DIGEST_DATA_DIR") {
+            self.digest.data_dir = PathBuf::from(v);
+        }
+        Ok(())
+    }
+
+    /// Check value ranges, naming the offending key on failure.
+    pub fn validate(&self) -> Result<()> {
+        if self.digest.cutoff_hours <= 0 {
+            bail!("invalid config: `digest.cutoff_hours` must be greater than 0");
+        }
+        if self.fetch.page_size == 0 {
+            bail!("invalid config: `fetch.page_size` must be greater than 0");
+        }
+        if self.fetch.max_latest_pages == 0 {
+            bail!("invalid config: `fetch.max_latest_pages` must be greater than 0");
+        }
+        if self.fetch.requests_per_sec.is_nan() || self.fetch.requests_per_sec <= 0.0 {
+            bail!("invalid config: `fetch.requests_per_sec` must be greater than 0");
+        }
+        if self.fetch.max_requests_per_host == 0 {
+            bail!("invalid config: `fetch.max_requests_per_host` must be greater than 0");
+        }
+        if self.fetch.max_retry_after_secs > self.fetch.max_elapsed_secs {
+            bail!(
+                "invalid config: `fetch.max_retry_after_secs` must not exceed `fetch.max_elapsed_secs`"
+            );
+        }
+        if self.fetch.concurrency == 0 {
+            bail!("invalid config: `fetch.concurrency` must be greater than 0");
+        }
+        if self.context_room() < MIN_CHUNK_TOKENS {
+            bail!(
+                "invalid config: `summarize.context_tokens` leaves fewer than {MIN_CHUNK_TOKENS} tokens for thread text after the system prompt and `summarize.reply_tokens`"
+            );
+        }
+        if self.summarize.chunk_max_tokens == Some(0) {
+            bail!("invalid config: `summarize.chunk_max_tokens` must be greater than 0");
+        }
+        if self.summarize.max_posts_for_chunk == 0 {
+            bail!("invalid config: `summarize.max_posts_for_chunk` must be greater than 0");
+        }
+        if self.summarize.token_budget == Some(0) {
+            bail!("invalid config: `summarize.token_budget` must be greater than 0");
+        }
+        if self.summarize.max_depth == 0 {
+            bail!("invalid config: `summarize.max_depth` must be greater than 0");
+        }
+        if self.summarize.timeout_secs == 0 {
+            bail!("invalid config: `summarize.timeout_secs` must be greater than 0");
+        }
+        if self.summarize.use_model_system_prompt && self.summarize.system_prompt.is_some() {
+            bail!(
+                "invalid config: `summarize.system_prompt` is ignored when `summarize.use_model_system_prompt` is set"
+            );
+        }
+        if self.summarize.concurrency == 0 {
+            bail!("invalid config: `summarize.concurrency` must be greater than 0");
+        }
+        if !self.ollama.base_url.starts_with("http") {
+            bail!("invalid config: `ollama.base_url` must be an http(s) URL");
+        }
+        if self.ollama.model.is_empty() {
+            bail!("invalid config: `ollama.model` must not be empty");
+        }
+        if self.ollama.max_output_tokens == Some(0) {
+            bail!("invalid config: `ollama.max_output_tokens` must be greater than 0");
+        }
+        validate_options("ollama.options", &self.ollama.options)?;
+        for (kind, options) in &self.ollama.prompt_options {
+            let key = format!("ollama.prompt_options.{}", kind.as_str());
+            validate_options(&key, options)?;
+        }
+        if !self.openai.base_url.starts_with("http") {
+            bail!("invalid config: `openai.base_url` must be an http(s) URL");
+        }
+        if self.openai.model.is_empty() {
+            bail!("invalid config: `openai.model` must not be empty");
+        }
+        if !self.llamacpp.base_url.starts_with("http") {
+            bail!("invalid config: `llamacpp.base_url` must be an http(s) URL");
+        }
+        if self.llamacpp.n_predict == 0 {
+            bail!("invalid config: `llamacpp.n_predict` must not be 0");
+        }
+        if self.llamacpp.grammar.is_some() && self.llamacpp.json_schema.is_some() {
+            bail!("invalid config: set only one of `llamacpp.grammar` and `llamacpp.json_schema`");
+        }
+        if self.forums.is_empty() {
+            bail!("invalid config: `forums` must list at least one forum");
+        }
+        for (i, forum) in self.forums.iter().enumerate() {
+            if forum.name.is_empty() {
+                bail!("invalid config: `forums[{i}].name` must not be empty");
+            }
+            if !forum.base_url.starts_with("http") {
+                bail!("invalid config: `forums[{i}].base_url` must be an http(s) URL");
+            }
+            // Digest items and default output directories are keyed by slug.
+            if self.forums[..i].iter().any(|f| f.slug() == forum.slug()) {
+                bail!("invalid config: `forums[{i}].name` must be unique");
+            }
+            let filter = &forum.filter;
+            let names = [
+                &filter.categories,
+                &filter.exclude_categories,
+                &filter.tags,
+                &filter.exclude_tags,
+            ];
+            if names.iter().any(|l| l.iter().any(String::is_empty)) {
+                bail!("invalid config: `forums[{i}].filter` must not list empty slugs or tags");
+            }
+            if filter.use_listings && filter.categories.is_empty() && filter.tags.is_empty() {
+                bail!(
+                    "invalid config: `forums[{i}].filter.use_listings` needs `categories` or `tags`"
+                );
+            }
+            // Keys in a secrets file are only checked when they are read.
+            if let Some(auth) = &forum.auth
+                && auth.secrets_file.is_none()
+            {
+                auth.credentials()
+                    .map_err(|e| anyhow!("invalid config: `forums[{i}].auth`: {e}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Model of the selected backend.
+    pub fn model(&self) -> &str {
+        match self.summarize.backend {
+            Backend::Ollama => &self.ollama.model,
+            Backend::OpenAi => &self.openai.model,
+            Backend::LlamaCpp => &self.llamacpp.model,
+            Backend::Mock => "mock",
+        }
+    }
+
+    /// Set the model of the selected backend.
+    pub fn set_model(&mut self, model: String) {
+        match self.summarize.backend {
+            Backend::Ollama => self.ollama.model = model,
+            Backend::OpenAi => self.openai.model = model,
+            Backend::LlamaCpp => self.llamacpp.model = model,
+            Backend::Mock => {}
+        }
+    }
+
+    /// System prompt sent with every request, or `None` to defer to the model.
+    pub fn system_prompt(&self) -> Option<&str> {
+        if self.summarize.use_model_system_prompt {
+            return None;
+        }
+        let builtin = if self.summarize.structured {
+            STRUCTURED_SYSTEM_PROMPT
+        } else {
+            SYSTEM_PROMPT
+        };
+        Some(self.summarize.system_prompt.as_deref().unwrap_or(builtin))
+    }
+
+    /// Size prompts to what Ollama reports for the model: its `Modelfile`
+    /// `num_ctx` replaces `summarize.context_tokens`, and neither may exceed
+    /// the length the model was trained with.
+    pub fn apply_model_info(&mut self, info: &ModelInfo) {
+        let context = info.num_ctx().unwrap_or(self.summarize.context_tokens);
+        self.summarize.context_tokens = info.context_length.map_or(context, |max| context.min(max));
+    }
+
+    /// Context window of the selected model in tokens.
+    pub fn context_tokens(&self) -> usize {
+        match (self.summarize.backend, self.ollama.options.num_ctx) {
+            (Backend::Ollama, Some(n)) => n as usize,
+            _ => self.summarize.context_tokens,
+        }
+    }
+
+    /// Max tokens of thread text, or of partial summaries, in one prompt: the
+    /// context window less the system prompt, prompt template and reply,
+    /// capped by `summarize.chunk_max_tokens`. Counts are cl100k estimates.
+    pub fn chunk_tokens(&self) -> usize {
+        let room = self.context_room();
+        self.summarize
+            .chunk_max_tokens
+            .map_or(room, |max| max.min(room))
+    }
+
+    /// Max tokens of thread text summarized per topic:
+    /// `summarize.token_budget`, or `max_depth` levels of [`REDUCE_FAN_IN`]
+    /// chunks of [`Config::chunk_tokens`].
+    pub fn token_budget(&self) -> usize {
+        self.summarize
+            .token_budget
+            .unwrap_or_else(|| self.chunk_tokens() * REDUCE_FAN_IN * self.summarize.max_depth)
+    }
+
+    fn context_room(&self) -> usize {
+        let overhead = count_tokens(self.system_prompt().unwrap_or_default())
+            + count_tokens(PROMPT_TEMPLATE).max(count_tokens(REDUCE_TEMPLATE))
+            + self.summarize.reply_tokens;
+        self.context_tokens().saturating_sub(overhead)
+    }
+
+    pub fn store_path(&self) -> PathBuf {
+        self.digest.data_dir.join("digest.sqlite")
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.digest.data_dir.join("run-report.json")
+    }
+
+    pub fn rate_limit(&self) -> RateLimit {
+        RateLimit {
+            requests_per_sec: self.fetch.requests_per_sec,
+            burst: self.fetch.burst,
+            max_requests_per_host: self.fetch.max_requests_per_host,
+            max_elapsed: Duration::from_secs(self.fetch.max_elapsed_secs),
+            max_retry_after: Duration::from_secs(self.fetch.max_retry_after_secs),
+        }
+    }
+
+    pub fn ollama_max_elapsed(&self) -> Duration {
+        Duration::from_secs(self.ollama.max_elapsed_secs)
+    }
+}
+
+fn validate_options(key: &str, options: &Options) -> Result<()> {
+    if options.temperature.is_some_and(|t| t.is_nan() || t < 0.0) {
+        bail!("invalid config: `{key}.temperature` must not be negative");
+    }
+    if options
+        .top_p
+        .is_some_and(|p| p.is_nan() || p <= 0.0 || p > 1.0)
+    {
+        bail!("invalid config: `{key}.top_p` must be in (0, 1]");
+    }
+    if options.num_ctx == Some(0) {
+        bail!("invalid config: `{key}.num_ctx` must be greater than 0");
+    }
+    if options.num_predict == Some(0) {
+        bail!("invalid config: `{key}.num_predict` must not be 0");
+    }
+    Ok(())
+}
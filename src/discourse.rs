@@ -0,0 +1,491 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use serde::{Deserialize, Deserializer};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::Post;
+use crate::http::{RateLimitedClient, is_status};
+
+/// User agent sent unless [`DiscourseClient::with_user_agent`] replaces it.
+pub const DEFAULT_USER_AGENT: &str = concat!("zc-forum-etl/", env!("CARGO_PKG_VERSION"));
+
+/// String kept out of logs: `Debug` prints a placeholder.
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// How requests to a forum are authenticated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Credentials {
+    /// Admin or granular API key acting as `username`, sent as `Api-Key` and
+    /// `Api-Username`.
+    ApiKey { key: Secret, username: String },
+    /// Key a user granted to this client, sent as `User-Api-Key`.
+    UserApiKey { key: Secret },
+}
+
+impl Credentials {
+    fn describe(&self) -> &'static str {
+        match self {
+            Credentials::ApiKey { .. } => "API key",
+            Credentials::UserApiKey { .. } => "user API key",
+        }
+    }
+}
+
+/// Page of `/latest.json` or a category or tag listing.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TopicList {
+    pub topics: Vec<TopicStub>,
+    /// Relative URL of the next page, absent on the last one.
+    #[serde(default)]
+    pub more_topics_url: Option<String>,
+}
+
+/// Topic as it appears in a listing.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TopicStub {
+    pub id: u64,
+    pub title: String,
+    #[serde(default)]
+    pub highest_post_number: Option<u64>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub bumped_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_posted_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub posts_count: u64,
+    #[serde(default)]
+    pub category_id: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl TopicStub {
+    /// Most recent activity Discourse reports for the topic.
+    pub fn last_activity(&self) -> Option<OffsetDateTime> {
+        self.bumped_at.max(self.last_posted_at)
+    }
+
+    /// Whether the listing already shows the topic has no posts since
+    /// `cutoff`. Topics without timestamps are never considered stale.
+    pub fn is_stale(&self, cutoff: OffsetDateTime) -> bool {
+        self.last_posted_at
+            .or(self.bumped_at)
+            .is_some_and(|t| t < cutoff)
+    }
+}
+
+/// Discourse lists tags either as plain names or, on newer versions, as
+/// `{id, name, slug}` objects.
+fn deserialize_tags<'de, D>(de: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tag {
+        Name(String),
+        Object { name: String },
+    }
+    let tags = Option::<Vec<Tag>>::deserialize(de)?.unwrap_or_default();
+    Ok(tags
+        .into_iter()
+        .map(|t| match t {
+            Tag::Name(name) | Tag::Object { name } => name,
+        })
+        .collect())
+}
+
+/// `/t/{id}.json`, or the subset of posts returned by `/t/{id}/posts.json`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Topic {
+    pub post_stream: PostStream,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PostStream {
+    pub posts: Vec<Post>,
+    /// Ids of every post in the topic, in post-number order. Only present on
+    /// `/t/{id}.json`.
+    #[serde(default)]
+    pub stream: Vec<u64>,
+}
+
+/// Entry of `/categories.json`.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Category {
+    pub id: u64,
+    pub name: String,
+    pub slug: String,
+    #[serde(default)]
+    pub parent_category_id: Option<u64>,
+    /// Only visible to some groups.
+    #[serde(default)]
+    pub read_restricted: bool,
+    #[serde(default)]
+    pub topic_count: u64,
+}
+
+/// Slug of category `id` prefixed with its parent's, as in `parent/child`.
+pub fn category_path(categories: &[Category], id: u64) -> Option<String> {
+    let category = categories.iter().find(|c| c.id == id)?;
+    let parent = category
+        .parent_category_id
+        .and_then(|p| categories.iter().find(|c| c.id == p));
+    Some(match parent {
+        Some(parent) => format!("{}/{}", parent.slug, category.slug),
+        None => category.slug.clone(),
+    })
+}
+
+/// Whether topics in category `id` must stay out of public digests.
+///
+/// Read-restricted categories are private. With `authenticated` requests, a
+/// topic without a category, or in one missing from `categories`, may be
+/// members-only too, so it is treated as private.
+pub fn is_private_category(categories: &[Category], id: Option<u64>, authenticated: bool) -> bool {
+    match id.and_then(|id| categories.iter().find(|c| c.id == id)) {
+        Some(c) => c.read_restricted,
+        None => authenticated,
+    }
+}
+
+/// Topic listing of a forum.
+#[derive(Clone, Copy, Debug)]
+pub enum Listing<'a> {
+    /// `/latest.json`.
+    Latest,
+    /// `/c/{slug}/{id}/l/latest.json`: a category and its subcategories.
+    Category(&'a Category),
+    /// `/tag/{tag}.json`.
+    Tag(&'a str),
+}
+
+impl Listing<'_> {
+    pub fn path(&self) -> String {
+        match self {
+            Listing::Latest => "/latest.json".to_string(),
+            Listing::Category(c) => format!("/c/{}/{}/l/latest.json", c.slug, c.id),
+            Listing::Tag(tag) => format!("/tag/{tag}.json"),
+        }
+    }
+}
+
+/// Entry of `/tags.json`.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "RawTag")]
+pub struct Tag {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Older versions name tags in `id` and `text`, newer ones in `name` with a
+/// numeric `id`.
+#[derive(Deserialize)]
+struct RawTag {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    count: u64,
+}
+
+impl From<RawTag> for Tag {
+    fn from(raw: RawTag) -> Self {
+        Tag {
+            name: raw.name.or(raw.text).unwrap_or_default(),
+            count: raw.count,
+        }
+    }
+}
+
+/// Public profile from `/u/{username}.json`.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub trust_level: u8,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub moderator: bool,
+}
+
+#[derive(Deserialize)]
+struct Latest {
+    topic_list: TopicList,
+}
+
+#[derive(Deserialize)]
+struct Categories {
+    category_list: CategoryList,
+}
+
+#[derive(Deserialize)]
+struct CategoryList {
+    categories: Vec<CategoryEntry>,
+}
+
+/// Subcategories come nested under their parent.
+#[derive(Deserialize)]
+struct CategoryEntry {
+    #[serde(flatten)]
+    category: Category,
+    #[serde(default)]
+    subcategory_list: Vec<Category>,
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    tags: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct UserResp {
+    user: User,
+}
+
+/// Typed client for one Discourse forum's JSON API.
+///
+/// Requests go through a shared [`RateLimitedClient`], so every client for
+/// the same host draws from one rate limit and request budget.
+#[derive(Clone)]
+pub struct DiscourseClient {
+    http: Arc<RateLimitedClient>,
+    base_url: String,
+    headers: HeaderMap,
+    /// Kind of credentials sent, for error messages.
+    auth: Option<&'static str>,
+}
+
+impl DiscourseClient {
+    pub fn new(http: Arc<RateLimitedClient>, base_url: &str) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+        DiscourseClient {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers,
+            auth: None,
+        }
+    }
+
+    /// Authenticate every request. Keys are marked sensitive so they stay out
+    /// of debug output.
+    pub fn with_credentials(mut self, credentials: &Credentials) -> Result<Self> {
+        match credentials {
+            Credentials::ApiKey { key, username } => {
+                self.set_header("api-key", key.expose(), true)?;
+                self.set_header("api-username", username, false)?;
+            }
+            Credentials::UserApiKey { key } => {
+                self.set_header("user-api-key", key.expose(), true)?;
+            }
+        }
+        self.auth = Some(credentials.describe());
+        Ok(self)
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.auth.is_some()
+    }
+
+    pub fn with_user_agent(mut self, user_agent: &str) -> Result<Self> {
+        self.set_header(USER_AGENT.as_str(), user_agent, false)?;
+        Ok(self)
+    }
+
+    fn set_header(&mut self, name: &str, value: &str, sensitive: bool) -> Result<()> {
+        let mut value =
+            HeaderValue::from_str(value).with_context(|| format!("invalid `{name}` header"))?;
+        value.set_sensitive(sensitive);
+        self.headers
+            .insert(HeaderName::from_bytes(name.as_bytes())?, value);
+        Ok(())
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Requests sent so far to the forum's host, retries included.
+    pub fn requests_sent(&self) -> usize {
+        self.http.requests_sent(&self.base_url)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let result = self
+            .http
+            .get_json_with_headers(&self.url(path), query, &self.headers)
+            .await;
+        match (result, self.auth) {
+            (Err(e), Some(auth))
+                if is_status(&e, StatusCode::FORBIDDEN)
+                    || is_status(&e, StatusCode::UNAUTHORIZED) =>
+            {
+                let path = path.split('?').next().unwrap_or(path);
+                Err(e.context(format!(
+                    "{} refused the {auth} for {path}; check that the key is valid and has the scope this request needs",
+                    self.base_url
+                )))
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Absolute URL of `path`, which may carry its own query string.
+    fn url(&self, path: &str) -> String {
+        let sep = if path.starts_with('/') { "" } else { "/" };
+        format!("{}{sep}{path}", self.base_url)
+    }
+
+    /// First page of `/latest.json`.
+    pub async fn latest(&self) -> Result<TopicList> {
+        self.listing(&Listing::Latest).await
+    }
+
+    /// First page of `listing`.
+    pub async fn listing(&self, listing: &Listing<'_>) -> Result<TopicList> {
+        Ok(self.get::<Latest>(&listing.path(), &[]).await?.topic_list)
+    }
+
+    /// Page a listing's `more_topics_url` points to, such as
+    /// `/latest?page=1`.
+    pub async fn more(&self, more_topics_url: &str) -> Result<TopicList> {
+        let path = json_page_path(more_topics_url);
+        Ok(self.get::<Latest>(&path, &[]).await?.topic_list)
+    }
+
+    /// Read `/latest.json`, following `more_topics_url` until a page ends with
+    /// a topic last active before `cutoff` or `max_pages` pages have been
+    /// read.
+    pub async fn latest_since(
+        &self,
+        cutoff: OffsetDateTime,
+        max_pages: usize,
+    ) -> Result<Vec<TopicStub>> {
+        self.listing_since(&Listing::Latest, cutoff, max_pages)
+            .await
+    }
+
+    /// Like [`DiscourseClient::latest_since`] for any listing.
+    pub async fn listing_since(
+        &self,
+        listing: &Listing<'_>,
+        cutoff: OffsetDateTime,
+        max_pages: usize,
+    ) -> Result<Vec<TopicStub>> {
+        let mut topics = Vec::new();
+        let mut page = self.listing(listing).await?;
+        for n in 1..=max_pages {
+            // Pinned topics are listed first regardless of activity.
+            let reached_cutoff = page
+                .topics
+                .iter()
+                .rfind(|t| !t.pinned)
+                .and_then(TopicStub::last_activity)
+                .is_none_or(|t| t < cutoff);
+            topics.extend(page.topics);
+            let Some(more) = page.more_topics_url.filter(|_| !reached_cutoff) else {
+                break;
+            };
+            if n == max_pages {
+                warn!(
+                    "Stopped paging {}{} after {max_pages} pages",
+                    self.base_url,
+                    listing.path()
+                );
+                break;
+            }
+            page = self.more(&more).await?;
+        }
+        Ok(topics)
+    }
+
+    /// `/t/{id}.json`: the first posts of a topic and the ids of all of them.
+    pub async fn topic(&self, id: u64) -> Result<Topic> {
+        self.get(&format!("/t/{id}.json"), &[]).await
+    }
+
+    /// Posts of topic `topic_id` with the given ids.
+    pub async fn posts(&self, topic_id: u64, ids: &[u64]) -> Result<Vec<Post>> {
+        let query: Vec<(&str, String)> =
+            ids.iter().map(|p| ("post_ids[]", p.to_string())).collect();
+        let topic: Topic = self
+            .get(&format!("/t/{topic_id}/posts.json"), &query)
+            .await?;
+        Ok(topic.post_stream.posts)
+    }
+
+    /// Categories visible to the client, subcategories included.
+    pub async fn categories(&self) -> Result<Vec<Category>> {
+        let query = [("include_subcategories", "true".to_string())];
+        let resp: Categories = self.get("/categories.json", &query).await?;
+        Ok(resp
+            .category_list
+            .categories
+            .into_iter()
+            .flat_map(|c| std::iter::once(c.category).chain(c.subcategory_list))
+            .collect())
+    }
+
+    pub async fn tags(&self) -> Result<Vec<Tag>> {
+        Ok(self.get::<Tags>("/tags.json", &[]).await?.tags)
+    }
+
+    pub async fn user(&self, username: &str) -> Result<User> {
+        let resp: UserResp = self.get(&format!("/u/{username}.json"), &[]).await?;
+        Ok(resp.user)
+    }
+}
+
+/// Turn a relative `more_topics_url` such as `/latest?page=1` into the path
+/// of its JSON representation.
+fn json_page_path(more: &str) -> String {
+    let (path, query) = more.split_once('?').unwrap_or((more, ""));
+    let path = if path.ends_with(".json") {
+        path.to_string()
+    } else {
+        format!("{path}.json")
+    };
+    if query.is_empty() {
+        path
+    } else {
+        format!("{path}?{query}")
+    }
+}
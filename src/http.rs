@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use backoff::{ExponentialBackoff, future::retry};
+use reqwest::{
+    Client, StatusCode, Url,
+    header::{HeaderMap, RETRY_AFTER},
+};
+use serde::de::DeserializeOwned;
+use time::{OffsetDateTime, format_description::well_known::Rfc2822};
+use tokio::time::{Instant, sleep_until};
+use tracing::warn;
+
+/// Limits applied per host by [`RateLimitedClient`].
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// Sustained request rate.
+    pub requests_per_sec: f64,
+    /// Requests that may be sent back to back before the rate applies.
+    pub burst: u32,
+    /// Hard cap on requests per host over the client's lifetime.
+    pub max_requests_per_host: usize,
+    /// Max retry duration for a single request.
+    pub max_elapsed: Duration,
+    /// Longest `Retry-After` honoured; longer delays are cut to this. Kept
+    /// below `max_elapsed` so that a retry still fits after the wait.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            requests_per_sec: 1.0,
+            burst: 3,
+            max_requests_per_host: 2_000,
+            max_elapsed: Duration::from_secs(60),
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Token bucket that hands out reservations instead of rejecting callers:
+/// every call to [`TokenBucket::reserve`] takes a token, possibly driving the
+/// balance negative, and returns the instant at which the caller may proceed.
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+    /// Set by `Retry-After`; nothing is released before it.
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(requests_per_sec: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        TokenBucket {
+            rate: requests_per_sec,
+            capacity,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                updated: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    pub fn reserve(&self) -> Instant {
+        let mut st = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(st.updated).as_secs_f64();
+        st.tokens = (st.tokens + elapsed * self.rate).min(self.capacity);
+        st.updated = now;
+        st.tokens -= 1.0;
+        let ready = if st.tokens >= 0.0 {
+            now
+        } else {
+            now + Duration::from_secs_f64(-st.tokens / self.rate)
+        };
+        match st.paused_until {
+            Some(p) if p > ready => p,
+            _ => ready,
+        }
+    }
+
+    /// Wait until a token is available.
+    pub async fn acquire(&self) {
+        sleep_until(self.reserve()).await;
+    }
+
+    /// Hold back every caller for `delay`, e.g. after a 429.
+    pub fn pause(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut st = self.state.lock().unwrap();
+        if st.paused_until.is_none_or(|p| p < until) {
+            st.paused_until = Some(until);
+        }
+    }
+}
+
+/// Parse a `Retry-After` value: either delay-seconds or an HTTP date.
+pub fn parse_retry_after(value: &str, now: OffsetDateTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = OffsetDateTime::parse(value, &Rfc2822).ok()?;
+    Some((at - now).try_into().unwrap_or(Duration::ZERO))
+}
+
+struct Host {
+    bucket: TokenBucket,
+    requests: Mutex<usize>,
+}
+
+/// HTTP client for polite scraping: a token bucket and request budget per
+/// host, `Retry-After` on 429/503, and exponential backoff for 5xx and
+/// transport errors. Other 4xx responses fail immediately with the
+/// underlying `reqwest::Error`, so callers can inspect the status.
+pub struct RateLimitedClient {
+    client: Client,
+    limits: RateLimit,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+impl RateLimitedClient {
+    pub fn new(client: Client, limits: RateLimit) -> Self {
+        RateLimitedClient {
+            client,
+            limits,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn host(&self, url: &Url) -> Arc<Host> {
+        let key = url.host_str().unwrap_or_default().to_string();
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    bucket: TokenBucket::new(self.limits.requests_per_sec, self.limits.burst),
+                    requests: Mutex::new(0),
+                })
+            })
+            .clone()
+    }
+
+    /// Requests sent so far to the host of `url`, retries included.
+    pub fn requests_sent(&self, url: &str) -> usize {
+        Url::parse(url)
+            .map(|u| *self.host(&u).requests.lock().unwrap())
+            .unwrap_or(0)
+    }
+
+    /// GET `url` with `query` appended and decode the JSON body.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        self.get_json_with_headers(url, query, &HeaderMap::new())
+            .await
+    }
+
+    /// Like [`RateLimitedClient::get_json`], sending `headers` with every
+    /// attempt.
+    pub async fn get_json_with_headers<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, String)],
+        headers: &HeaderMap,
+    ) -> Result<T> {
+        let url = Url::parse(url)?;
+        let host = self.host(&url);
+        let start = Instant::now();
+        let backoff = ExponentialBackoff {
+            max_elapsed_time: Some(self.limits.max_elapsed),
+            ..Default::default()
+        };
+        let op = || {
+            let url = url.clone();
+            let host = host.clone();
+            async move {
+                {
+                    let mut requests = host.requests.lock().unwrap();
+                    if *requests >= self.limits.max_requests_per_host {
+                        return Err(backoff::Error::permanent(anyhow!(
+                            "request budget of {} exhausted for {}",
+                            self.limits.max_requests_per_host,
+                            url.host_str().unwrap_or_default()
+                        )));
+                    }
+                    *requests += 1;
+                }
+                host.bucket.acquire().await;
+
+                let resp = self
+                    .client
+                    .get(url.clone())
+                    .headers(headers.clone())
+                    .query(query)
+                    .send()
+                    .await
+                    .map_err(|e| backoff::Error::transient(anyhow!("transport: {e:?}")))?;
+
+                let status = resp.status();
+                if status == StatusCode::TOO_MANY_REQUESTS
+                    || status == StatusCode::SERVICE_UNAVAILABLE
+                {
+                    let delay = resp
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| parse_retry_after(v, OffsetDateTime::now_utc()));
+                    let err = anyhow!("http {status} from {url}");
+                    let Some(delay) = delay else {
+                        return Err(backoff::Error::transient(err));
+                    };
+                    // backoff skips its elapsed-time check for explicit
+                    // delays, so the budget is enforced here.
+                    let remaining = self.limits.max_elapsed.saturating_sub(start.elapsed());
+                    if remaining.is_zero() {
+                        return Err(backoff::Error::permanent(err));
+                    }
+                    let d = delay.min(self.limits.max_retry_after).min(remaining);
+                    warn!("{status} from {url}, retrying after {d:?}");
+                    host.bucket.pause(d);
+                    return Err(backoff::Error::retry_after(err, d));
+                }
+                if status.is_server_error() {
+                    return Err(backoff::Error::transient(anyhow!(
+                        "http {status} from {url}"
+                    )));
+                }
+                let resp = resp
+                    .error_for_status()
+                    .map_err(|e| backoff::Error::permanent(e.into()))?;
+                resp.json::<T>()
+                    .await
+                    .map_err(|e| backoff::Error::permanent(anyhow!("decode {url}: {e}")))
+            }
+        };
+        retry(backoff, op).await
+    }
+}
+
+/// Whether `err` is an HTTP error response with the given status.
+pub fn is_status(err: &anyhow::Error, status: StatusCode) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        == Some(status)
+}
@@ -1,12 +1,32 @@
+use anyhow::{Context, Result, bail};
 use html5ever::tendril::TendrilSink;
 use markup5ever_rcdom::{Handle, NodeData, RcDom};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 use tiktoken_rs::{CoreBPE, cl100k_base};
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 
+pub mod config;
+pub mod discourse;
+pub mod http;
+pub mod llamacpp;
+pub mod mapreduce;
 pub mod ollama;
+pub mod openai;
+pub mod prompt;
+pub mod render;
+pub mod report;
+pub mod store;
+pub mod structured;
+pub mod summarizer;
+use config::{ForumAuth, ForumFilter};
 pub use ollama::summarize_with_ollama;
+pub use prompt::{PROMPT_TEMPLATE, build_prompt};
+use structured::StructuredSummary;
+use summarizer::count_tokens;
 
 pub static BPE: LazyLock<CoreBPE> =
     LazyLock::new(|| cl100k_base().expect("Failed to initialize cl100k_base tokenizer"));
@@ -125,13 +145,16 @@ pub fn take_prefix_chars(s: &str, max_chars: usize) -> String {
     s.chars().take(max_chars).collect()
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Post {
     pub id: u64,
     pub cooked: String,
     #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     pub username: String,
+    /// Position of the post within its topic (1-based).
+    #[serde(default)]
+    pub post_number: u64,
 }
 
 pub fn posts_to_chunk<'a>(posts: impl Iterator<Item = &'a Post>, max_chars: usize) -> String {
@@ -160,15 +183,251 @@ pub fn posts_to_chunk<'a>(posts: impl Iterator<Item = &'a Post>, max_chars: usiz
     out
 }
 
-#[derive(Clone)]
+/// Pack posts into consecutive chunks of at most `max_tokens` cl100k tokens,
+/// one `[post:ID @ TIME]` line per post. Posts are never split across chunks
+/// unless a single post exceeds `max_tokens`; such a post is split at
+/// sentence boundaries, or between words for run-on sentences, into lines
+/// that each repeat its header.
+pub fn posts_to_token_chunks<'a>(
+    posts: impl Iterator<Item = &'a Post>,
+    max_tokens: usize,
+) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut cur = String::new();
+    let mut cur_tokens = 0usize;
+    for p in posts {
+        let t = strip_tags_fast(&p.cooked);
+        if t.is_empty() {
+            continue;
+        }
+        let Ok(ts) = p.created_at.format(&Rfc3339) else {
+            continue;
+        };
+        let header = format!("[post:{} @ {}]", p.id, ts);
+        for line in split_post(&header, &t, max_tokens) {
+            // One more for the newline.
+            let tokens = count_tokens(&line) + 1;
+            if !cur.is_empty() && cur_tokens + tokens > max_tokens {
+                chunks.push(std::mem::take(&mut cur));
+                cur_tokens = 0;
+            }
+            if !cur.is_empty() {
+                cur.push('\n');
+            }
+            cur.push_str(&line);
+            cur_tokens += tokens;
+        }
+    }
+    if !cur.is_empty() {
+        chunks.push(cur);
+    }
+    chunks
+}
+
+/// `header text` as one line, or as several lines of at most `max_tokens`
+/// tokens each when it does not fit.
+fn split_post(header: &str, text: &str, max_tokens: usize) -> Vec<String> {
+    let line = format!("{header} {text}");
+    if count_tokens(&line) < max_tokens {
+        return vec![line];
+    }
+    let room = max_tokens.saturating_sub(count_tokens(header) + 2).max(1);
+    let mut units = Vec::new();
+    for sentence in sentences(text) {
+        if count_tokens(sentence) < room {
+            units.push(sentence.to_string());
+            continue;
+        }
+        for word in sentence.split(' ') {
+            if count_tokens(word) < room {
+                units.push(word.to_string());
+            } else {
+                // A single word over budget, e.g. a long URL, is cut.
+                let chars: Vec<char> = word.chars().collect();
+                let size = room.saturating_sub(1).max(1);
+                units.extend(chars.chunks(size).map(|c| c.iter().collect::<String>()));
+            }
+        }
+    }
+
+    // Token counts of the parts, plus one for each joining space, bound the
+    // count of the joined text.
+    let mut pieces = Vec::new();
+    let mut cur = String::new();
+    let mut cur_tokens = 0usize;
+    for unit in units {
+        let tokens = count_tokens(&unit) + 1;
+        if !cur.is_empty() && cur_tokens + tokens > room {
+            pieces.push(std::mem::take(&mut cur));
+            cur_tokens = 0;
+        }
+        if !cur.is_empty() {
+            cur.push(' ');
+        }
+        cur.push_str(&unit);
+        cur_tokens += tokens;
+    }
+    if !cur.is_empty() {
+        pieces.push(cur);
+    }
+    pieces.iter().map(|p| format!("{header} {p}")).collect()
+}
+
+/// Split whitespace-normalized text after `.`, `!` or `?` followed by a space.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut prev_end = false;
+    for (i, c) in text.char_indices() {
+        if prev_end && c == ' ' {
+            out.push(&text[start..i]);
+            start = i + 1;
+        }
+        prev_end = matches!(c, '.' | '!' | '?');
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
+
+pub const DEFAULT_FORUM_URL: &str = "https://forum.zcashcommunity.com";
+
+/// Directory of the published site. Digests written below it are public.
+pub const PUBLIC_DIR: &str = "public";
+
+/// A Discourse instance the digest is built from.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Forum {
+    /// Display name, used in page titles and section headings.
+    pub name: String,
+    pub base_url: String,
+    /// Directory the per-forum `index.html` and `rss.xml` are written to.
+    #[serde(default)]
+    pub output_dir: PathBuf,
+    /// Categories, tags and topics to digest; everything listed by default.
+    #[serde(default)]
+    pub filter: ForumFilter,
+    /// Credentials for members-only categories; requests are anonymous
+    /// without.
+    #[serde(default)]
+    pub auth: Option<ForumAuth>,
+    /// Allow topics from read-restricted categories into a digest written
+    /// under [`PUBLIC_DIR`].
+    #[serde(default)]
+    pub publish_private: bool,
+}
+
+impl Forum {
+    /// Filesystem-friendly form of the forum name: lowercased, spaces
+    /// replaced by dashes.
+    pub fn slug(&self) -> String {
+        slugify(&self.name)
+    }
+
+    /// `public/<slug>`.
+    pub fn default_output_dir(name: &str) -> PathBuf {
+        PathBuf::from(PUBLIC_DIR).join(slugify(name))
+    }
+}
+
+/// Whether the existing directory `dir` lies in the published site under
+/// [`PUBLIC_DIR`], comparing canonical paths so that absolute paths, `..`
+/// and symlinks cannot hide it.
+pub fn is_public_dir(dir: &Path) -> Result<bool> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("resolving {}", dir.display()))?;
+    let public = match Path::new(PUBLIC_DIR).canonicalize() {
+        Ok(p) => p,
+        // Nothing lies in a site that does not exist.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("resolving {PUBLIC_DIR}")),
+    };
+    Ok(dir.starts_with(public))
+}
+
+fn slugify(name: &str) -> String {
+    name.to_lowercase().replace(' ', "-")
+}
+
+impl Default for Forum {
+    fn default() -> Self {
+        Forum {
+            name: "Zcash Forum".to_string(),
+            base_url: DEFAULT_FORUM_URL.to_string(),
+            output_dir: PathBuf::from(PUBLIC_DIR),
+            filter: ForumFilter::default(),
+            auth: None,
+            publish_private: false,
+        }
+    }
+}
+
+/// Parse a `;`-separated list of `name|base_url|output_dir` forum specs.
+///
+/// The output directory may be omitted, in which case it defaults to
+/// [`Forum::default_output_dir`].
+pub fn parse_forums(spec: &str) -> Result<Vec<Forum>> {
+    let mut forums = Vec::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split('|').map(str::trim);
+        let (Some(name), Some(base_url)) = (parts.next(), parts.next()) else {
+            bail!("invalid forum spec {entry:?}: expected name|base_url[|output_dir]");
+        };
+        if name.is_empty() || !base_url.starts_with("http") {
+            bail!("invalid forum spec {entry:?}: expected name|base_url[|output_dir]");
+        }
+        let output_dir = match parts.next() {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => Forum::default_output_dir(name),
+        };
+        forums.push(Forum {
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            output_dir,
+            filter: ForumFilter::default(),
+            auth: None,
+            publish_private: false,
+        });
+    }
+    if forums.is_empty() {
+        bail!("no forums configured");
+    }
+    Ok(forums)
+}
+
+/// Content-addressed key for a cached summary: the SHA-256 of model, system
+/// prompt, prompt template and chunk text, hex-encoded.
+pub fn summary_cache_key(model: &str, system: Option<&str>, template: &str, chunk: &str) -> String {
+    let mut hasher = Sha256::new();
+    // A missing system prompt hashes differently from an empty one.
+    hasher.update([u8::from(system.is_some())]);
+    for part in [model, system.unwrap_or_default(), template, chunk] {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DigestItem {
     pub post_id: u64,
     pub topic_id: u64,
+    #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
     pub author: String,
     pub title: String,
     pub url: String,
+    /// Plain-text summary, used by RSS and when no structured one exists.
     pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured: Option<StructuredSummary>,
 }
 
 pub fn build_post_url(base: &str, topic_id: u64, post_id: u64) -> String {
@@ -190,6 +449,7 @@ pub fn compose_digest_item(
         title: title.to_string(),
         url: build_post_url(base, topic_id, post.id),
         summary,
+        structured: None,
     }
 }
 
@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::summarizer::{Request, Summarizer, SummaryResult, Timings, count_tokens, post_json};
+
+/// `llama-server` endpoint used for summaries.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endpoint {
+    /// `/v1/chat/completions`; the server applies the model's chat template.
+    #[default]
+    Chat,
+    /// Native `/completion`; the prompt is sent as raw text.
+    Completion,
+}
+
+/// Sampling limits and output constraints passed to `llama-server`.
+#[derive(Clone, Debug, Default)]
+pub struct Params {
+    /// Max tokens to generate; negative means until end of stream.
+    pub n_predict: i32,
+    /// GBNF grammar the output must match.
+    pub grammar: Option<String>,
+    /// JSON schema the output must match; converted to a grammar by the server.
+    pub json_schema: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct CompletionReq<'a> {
+    prompt: &'a str,
+    n_predict: i32,
+    stream: bool,
+    cache_prompt: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grammar: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json_schema: Option<&'a serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct CompletionResp {
+    content: String,
+    #[serde(default)]
+    tokens_evaluated: Option<usize>,
+    #[serde(default)]
+    tokens_predicted: Option<usize>,
+    /// `eos`, `word` (a stop string) or `limit` (`n_predict` reached).
+    #[serde(default)]
+    stop_type: Option<String>,
+    /// Set by servers predating `stop_type` when `n_predict` was reached.
+    #[serde(default)]
+    stopped_limit: bool,
+    #[serde(default)]
+    timings: Option<ServerTimings>,
+}
+
+/// Timings `llama-server` reports with a reply, in milliseconds.
+#[derive(Deserialize, Clone, Copy)]
+struct ServerTimings {
+    #[serde(default)]
+    prompt_ms: f64,
+    #[serde(default)]
+    predicted_ms: f64,
+}
+
+impl From<ServerTimings> for Timings {
+    fn from(t: ServerTimings) -> Self {
+        let ms = |v: f64| Duration::from_secs_f64(v.max(0.0) / 1000.0);
+        Timings {
+            total: ms(t.prompt_ms + t.predicted_ms),
+            load: Duration::ZERO,
+            eval: ms(t.predicted_ms),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Msg<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChatReq<'a> {
+    model: &'a str,
+    stream: bool,
+    messages: Vec<Msg<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grammar: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json_schema: Option<&'a serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ChatResp {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+    #[serde(default)]
+    timings: Option<ServerTimings>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMsg,
+    /// `length` when `max_tokens` cut the reply off.
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMsg {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// Summarize `req.prompt` with a llama.cpp server, retrying transient
+/// failures for at most `max_elapsed`.
+///
+/// `req.system` goes out as a `system` message on the chat endpoint and is
+/// prepended to the raw prompt on `/completion`.
+///
+/// Token counts and timings are the ones the server reports, with cl100k
+/// estimates for counts it leaves out. A reply that hit `n_predict` comes
+/// back as truncated.
+pub async fn summarize_with_llamacpp(
+    client: &Client,
+    base: &str,
+    endpoint: Endpoint,
+    model: &str,
+    req: Request<'_>,
+    params: &Params,
+    max_elapsed: Duration,
+) -> Result<SummaryResult> {
+    let Request {
+        system,
+        prompt,
+        format,
+        ..
+    } = req;
+    let base = base.trim_end_matches('/');
+    // A requested output format replaces the configured constraint.
+    let (grammar, json_schema) = match format {
+        Some(schema) => (None, Some(schema)),
+        None => (params.grammar.as_deref(), params.json_schema.as_ref()),
+    };
+
+    let estimate = || count_tokens(system.unwrap_or_default()) + count_tokens(prompt);
+    let result = match endpoint {
+        Endpoint::Completion => {
+            let prompt = match system {
+                Some(system) => format!("{system}\n\n{prompt}"),
+                None => prompt.to_string(),
+            };
+            let body = CompletionReq {
+                prompt: &prompt,
+                n_predict: params.n_predict,
+                stream: false,
+                cache_prompt: true,
+                grammar,
+                json_schema,
+            };
+            let url = format!("{base}/completion");
+            let r: CompletionResp = post_json(client, &url, None, &body, max_elapsed).await?;
+            SummaryResult {
+                prompt_tokens: r.tokens_evaluated.unwrap_or_else(estimate),
+                completion_tokens: r
+                    .tokens_predicted
+                    .unwrap_or_else(|| count_tokens(&r.content)),
+                truncated: r.stopped_limit || r.stop_type.as_deref() == Some("limit"),
+                timings: r.timings.map(Timings::from),
+                text: r.content,
+            }
+        }
+        Endpoint::Chat => {
+            let mut messages = Vec::with_capacity(2);
+            if let Some(system) = system {
+                messages.push(Msg {
+                    role: "system",
+                    content: system,
+                });
+            }
+            messages.push(Msg {
+                role: "user",
+                content: prompt,
+            });
+            let body = ChatReq {
+                model,
+                stream: false,
+                messages,
+                max_tokens: (params.n_predict >= 0).then_some(params.n_predict),
+                grammar,
+                json_schema,
+            };
+            let url = format!("{base}/v1/chat/completions");
+            let r: ChatResp = post_json(client, &url, None, &body, max_elapsed).await?;
+            let choice = r
+                .choices
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("{url} returned no choices"))?;
+            let text = choice
+                .message
+                .content
+                .ok_or_else(|| anyhow!("{url} returned no content"))?;
+            let (prompt_tokens, completion_tokens) = match r.usage {
+                Some(u) => (u.prompt_tokens, u.completion_tokens),
+                None => (estimate(), count_tokens(&text)),
+            };
+            SummaryResult {
+                text,
+                prompt_tokens,
+                completion_tokens,
+                truncated: choice.finish_reason.as_deref() == Some("length"),
+                timings: r.timings.map(Timings::from),
+            }
+        }
+    };
+    Ok(result)
+}
+
+/// [`Summarizer`] backed by llama.cpp's `llama-server`.
+pub struct LlamaCppSummarizer {
+    pub client: Client,
+    pub base_url: String,
+    /// Label for runs and cache keys; `llama-server` serves a single model.
+    pub model: String,
+    pub endpoint: Endpoint,
+    pub params: Params,
+    /// Max retry duration for a single call.
+    pub max_elapsed: Duration,
+}
+
+impl Summarizer for LlamaCppSummarizer {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(summarize_with_llamacpp(
+            &self.client,
+            &self.base_url,
+            self.endpoint,
+            &self.model,
+            req,
+            &self.params,
+            self.max_elapsed,
+        ))
+    }
+}
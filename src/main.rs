@@ -1,221 +1,733 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration as StdDuration;
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use futures::future::join_all;
 use reqwest::{Client, StatusCode};
-use rss::{ChannelBuilder, ItemBuilder};
-use serde::Deserialize;
-use time::{Duration, OffsetDateTime, format_description::well_known::Rfc2822};
-use tokio::time::{sleep, timeout};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Semaphore;
 use tracing::{info, warn};
 use zc_forum_etl::{
-    Post, compose_digest_item, posts_to_chunk, strip_post_tags, summarize_with_ollama,
+    Forum, PUBLIC_DIR, Post, build_prompt, compose_digest_item,
+    config::{Backend, Config},
+    discourse::{
+        Category, DiscourseClient, Listing, TopicStub, category_path, is_private_category,
+    },
+    http::{RateLimitedClient, is_status},
+    is_public_dir,
+    mapreduce::{ThreadSummary, chunk_budget, summarize_thread_cached, within_budget},
+    ollama::{self, Modelfile},
+    posts_to_token_chunks,
+    prompt::{PromptKind, SYSTEM_PROMPT_VERSION},
+    render::{ForumDigest, write_digest},
+    report::{Failure, RunReport, Stage},
+    store::{Store, StoredTopic},
+    strip_post_tags,
+    summarizer::{self, Request, Summarizer},
 };
 
-const CHUNK_MAX_CHARS: usize = 1_800;
-const SUM_TIMEOUT_SECS: u64 = 240;
-const PAGE_SIZE: usize = 20;
-const MAX_POSTS_FOR_CHUNK: usize = 200;
-const CUTOFF_HOURS: i64 = 24;
-
-#[derive(Deserialize)]
-struct Latest {
-    topic_list: TopicList,
-}
-
-#[derive(Deserialize)]
-struct TopicList {
-    topics: Vec<TopicStub>,
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Summarize Discourse forum activity into an HTML and RSS digest"
+)]
+struct Cli {
+    /// Config file (defaults to `DIGEST_CONFIG`, then `digest.toml`).
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Only include posts from the last N hours.
+    #[arg(long, global = true)]
+    hours: Option<i64>,
+    /// Directory the digest is written to.
+    #[arg(long, global = true)]
+    output_dir: Option<PathBuf>,
+    /// Directory holding the SQLite store of topics, posts and summaries.
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+    /// Model used for summaries by the selected backend.
+    #[arg(long, global = true)]
+    model: Option<String>,
+    /// Fixed Ollama sampling seed, for reproducible summaries.
+    #[arg(long, global = true)]
+    seed: Option<i64>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Deserialize)]
-struct TopicStub {
-    id: u64,
-    title: String,
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Fetch topics with recent posts and store them in the data directory.
+    Fetch,
+    /// Summarize previously fetched topics.
+    Summarize,
+    /// Render HTML and RSS from stored summaries.
+    Render,
+    /// Fetch, summarize and render in one go (the default).
+    Run,
+    /// Drop cached summaries of the configured model (`--model`).
+    ClearCache {
+        /// Drop cached summaries of every model.
+        #[arg(long)]
+        all: bool,
+    },
 }
 
-#[derive(Deserialize)]
-struct TopicFull {
-    post_stream: PostStream,
-}
-
-#[derive(Deserialize)]
-struct PostStream {
-    posts: Vec<Post>,
+impl Cli {
+    fn apply(&self, config: &mut Config) {
+        if let Some(hours) = self.hours {
+            config.digest.cutoff_hours = hours;
+        }
+        if let Some(dir) = &self.data_dir {
+            config.digest.data_dir = dir.clone();
+        }
+        if let Some(model) = &self.model {
+            config.set_model(model.clone());
+        }
+        if let Some(seed) = self.seed {
+            config.ollama.options.seed = Some(seed);
+        }
+        if let Some(dir) = &self.output_dir {
+            if config.digest.merge_dir.is_some() {
+                config.digest.merge_dir = Some(dir.clone());
+            } else if let [forum] = config.forums.as_mut_slice() {
+                forum.output_dir = dir.clone();
+            } else {
+                for forum in &mut config.forums {
+                    forum.output_dir = dir.join(forum.slug());
+                }
+            }
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt().with_env_filter("info").init();
 
+    let cli = Cli::parse();
+    let mut config = Config::load(cli.config.as_deref())?;
+    cli.apply(&mut config);
+    config.validate()?;
+
     // HTTP client: generous timeouts for local servers.
     let client = Client::builder()
         .connect_timeout(StdDuration::from_secs(10))
         .timeout(StdDuration::from_secs(120))
         .build()?;
 
-    let model = std::env::var("LLM_MODEL").unwrap_or_else(|_| "qwen2.5:latest".to_string());
-    let ollama_base =
-        std::env::var("OLLAMA_BASE_URL").unwrap_or_else(|_| "http://127.0.0.1:11434".to_string());
-
-    // Warmup
-    let warm_prompt = build_prompt("warmup", "warmup");
-    if let Err(e) = summarize_with_ollama(&client, &ollama_base, &model, &warm_prompt).await {
-        warn!("Warm-up summarize_with_ollama failed: {e}");
+    let command = cli.command.unwrap_or(Command::Run);
+    if config.summarize.backend == Backend::Ollama
+        && matches!(command, Command::Summarize | Command::Run)
+    {
+        inspect_ollama_model(&client, &mut config).await?;
     }
 
-    let latest: Latest = fetch_latest(&client).await?;
-    info!("Fetched {} topics", latest.topic_list.topics.len());
+    let mut store = Store::open(&config.store_path())?;
 
-    let estimated_html_size = 2048 + latest.topic_list.topics.len() * 2048;
-    let mut html = String::with_capacity(estimated_html_size);
-    html.push_str("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Zcash Forum Digest</title><link rel=\"stylesheet\" href=\"./style.css\"></head><body>");
-    html.push_str(&format!(
-        "<h1>Zcash Forum Digest for {}</h1><p><a href=\"rss.xml\">RSS Feed</a></p>",
-        OffsetDateTime::now_utc().date()
-    ));
+    let forum_client = Arc::new(RateLimitedClient::new(client.clone(), config.rate_limit()));
+    let summarizer = summarizer::from_config(&config, client);
+
+    let mut report = RunReport::default();
+    match command {
+        Command::Fetch => fetch(&forum_client, &config, &mut store, &mut report).await?,
+        Command::Summarize => {
+            summarize(summarizer.as_ref(), &config, &mut store, &mut report).await?
+        }
+        Command::Render => return render(&config, &store),
+        Command::Run => {
+            fetch(&forum_client, &config, &mut store, &mut report).await?;
+            summarize(summarizer.as_ref(), &config, &mut store, &mut report).await?;
+            render(&config, &store)?;
+        }
+        Command::ClearCache { all } => {
+            let model = (!all).then_some(config.model());
+            let removed = store.invalidate_summaries(model)?;
+            info!("Removed {removed} cached summaries");
+            return Ok(());
+        }
+    }
 
-    let mut items = Vec::with_capacity(latest.topic_list.topics.len());
-    let cutoff = OffsetDateTime::now_utc() - Duration::hours(CUTOFF_HOURS);
+    let path = config.report_path();
+    report.write(&path)?;
+    if !report.failures.is_empty() {
+        warn!(
+            "{} topics failed; see {}",
+            report.failures.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
 
-    for stub in latest.topic_list.topics {
-        let posts = fetch_posts(&client, stub.id, cutoff).await?;
-        if posts.is_empty() {
-            continue;
+/// Make sure the configured model is installed, bootstrapping it when
+/// `ollama.bootstrap` is set, and size prompt budgets to the context window
+/// Ollama reports for it.
+async fn inspect_ollama_model(client: &Client, config: &mut Config) -> Result<()> {
+    let (base, model) = (&config.ollama.base_url, &config.ollama.model);
+    let max_elapsed = config.ollama_max_elapsed();
+    if !ollama::has_model(client, base, model, max_elapsed).await? {
+        if !config.ollama.bootstrap {
+            bail!(
+                "model `{model}` is not installed on {base}; run `ollama pull {model}` or `ollama create {model} -f Modelfile`, or set `ollama.bootstrap = true`"
+            );
         }
-        let last_post = posts.iter().max_by_key(|p| p.created_at).unwrap();
+        let path = &config.ollama.modelfile;
+        let modelfile = if path.exists() {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            Some(Modelfile::parse(&text).with_context(|| format!("parsing {}", path.display()))?)
+        } else {
+            None
+        };
+        ollama::bootstrap(client, base, model, modelfile.as_ref(), max_elapsed).await?;
+    }
+    let info = ollama::show_model(client, base, model, max_elapsed).await?;
+    info!(
+        "Model {model}: num_ctx {:?}, trained context {:?}, parameters {:?}",
+        info.num_ctx(),
+        info.context_length,
+        info.parameters
+    );
+    config.apply_model_info(&info);
+    config.validate()?;
+    info!(
+        "Context window {} tokens, {} per chunk",
+        config.context_tokens(),
+        config.chunk_tokens()
+    );
+    Ok(())
+}
 
-        let chunk = posts_to_chunk(posts.iter().take(MAX_POSTS_FOR_CHUNK), CHUNK_MAX_CHARS);
+fn cutoff(config: &Config) -> OffsetDateTime {
+    OffsetDateTime::now_utc() - Duration::hours(config.digest.cutoff_hours)
+}
 
-        let mut summary = String::new();
-        if !chunk.is_empty() {
-            let prompt = build_prompt(&stub.title, &chunk);
-            match timeout(
-                StdDuration::from_secs(SUM_TIMEOUT_SECS),
-                summarize_with_ollama(&client, &ollama_base, &model, &prompt),
+/// Fetch every forum's topics with posts inside the window into the store.
+///
+/// A forum whose listing cannot be read, or a topic that cannot be synced, is
+/// recorded in `report` and skipped; the run then finishes as `partial`.
+async fn fetch(
+    http: &Arc<RateLimitedClient>,
+    config: &Config,
+    store: &mut Store,
+    report: &mut RunReport,
+) -> Result<()> {
+    let run_id = store.begin_run("fetch", config.digest.cutoff_hours, config.model())?;
+    let outcome = fetch_forums(http, config, store, report, run_id).await;
+    finish_run(store, run_id, outcome)
+}
+
+/// Body of [`fetch`]; returns the number of failures recorded.
+async fn fetch_forums(
+    http: &Arc<RateLimitedClient>,
+    config: &Config,
+    store: &mut Store,
+    report: &mut RunReport,
+    run_id: i64,
+) -> Result<usize> {
+    let cutoff = cutoff(config);
+    let limit = Semaphore::new(config.fetch.concurrency);
+    let mut failed = 0;
+    let mut clients = Vec::with_capacity(config.forums.len());
+    for (i, forum) in config.forums.iter().enumerate() {
+        let client = DiscourseClient::new(http.clone(), &forum.base_url);
+        clients.push(match &forum.auth {
+            Some(auth) => {
+                let credentials = auth
+                    .credentials()
+                    .with_context(|| format!("`forums[{i}].auth`"))?;
+                client.with_credentials(&credentials)?
+            }
+            None => client,
+        });
+    }
+    for (forum, client) in config.forums.iter().zip(clients) {
+        let listing = async {
+            let categories = client.categories().await?;
+            let topics = list_topics(
+                &client,
+                forum,
+                &categories,
+                cutoff,
+                config.fetch.max_latest_pages,
             )
-            .await
-            {
-                Ok(Ok((s, _, _))) => summary = strip_post_tags(&s),
-                Ok(Err(e)) => warn!("LLM summarize failed for {}: {e}", stub.id),
-                Err(_) => warn!("LLM summarize timed out for {}", stub.id),
+            .await?;
+            anyhow::Ok((topics, categories))
+        };
+        let (mut topics, categories) = match listing.await {
+            Ok(listing) => listing,
+            Err(e) => {
+                warn!("Listing {} failed: {e:#}", forum.name);
+                let failure = Failure {
+                    forum: forum.base_url.clone(),
+                    topic_id: None,
+                    title: None,
+                    stage: Stage::Fetch,
+                    reason: format!("{e:#}"),
+                };
+                store.record_failure(run_id, &failure)?;
+                report.failures.push(failure);
+                failed += 1;
+                continue;
+            }
+        };
+        let label = |t: &TopicStub| t.category_id.and_then(|c| category_path(&categories, c));
+        let listed = topics.len();
+        topics.retain(|t| forum.filter.allows(t.id, label(t).as_deref(), &t.tags));
+        let (stale, active): (Vec<_>, Vec<_>) = topics.iter().partition(|t| t.is_stale(cutoff));
+        info!(
+            "Fetched {listed} topics from {} ({} filtered out, {} without posts in the window skipped)",
+            forum.name,
+            listed - topics.len(),
+            stale.len()
+        );
+
+        // Topics are fetched concurrently but written in listing order.
+        let reader = &*store;
+        let synced = join_all(active.iter().map(|stub| async {
+            let _permit = limit.acquire().await.expect("semaphore is never closed");
+            sync_topic(&client, reader, forum, stub, cutoff, config.fetch.page_size).await
+        }))
+        .await;
+        for (stub, result) in active.iter().zip(synced) {
+            let result = result.and_then(|update| {
+                if let Some(u) = update {
+                    store.upsert_topic(
+                        &forum.base_url,
+                        stub.id,
+                        &stub.title,
+                        &u.posts,
+                        u.high_water,
+                    )?;
+                    store.set_synced_since(&forum.base_url, stub.id, cutoff)?;
+                }
+                let is_private =
+                    is_private_category(&categories, stub.category_id, client.is_authenticated());
+                store.set_private(&forum.base_url, stub.id, is_private)?;
+                store.set_labels(&forum.base_url, stub.id, label(stub).as_deref(), &stub.tags)
+            });
+            match result {
+                Ok(()) => report.topics_fetched += 1,
+                Err(e) => {
+                    warn!("Fetching topic {} failed: {e:#}", stub.id);
+                    let failure = Failure {
+                        forum: forum.base_url.clone(),
+                        topic_id: Some(stub.id),
+                        title: Some(stub.title.clone()),
+                        stage: Stage::Fetch,
+                        reason: format!("{e:#}"),
+                    };
+                    store.record_failure(run_id, &failure)?;
+                    report.failures.push(failure);
+                    failed += 1;
+                }
             }
         }
+        info!("Sent {} requests to {}", client.requests_sent(), forum.name);
+    }
+    Ok(failed)
+}
 
-        let item_data = compose_digest_item(
-            "https://forum.zcashcommunity.com",
-            stub.id,
-            &stub.title,
-            last_post,
-            summary.clone(),
-        );
+/// Mark a run `ok`, `partial` when some topics failed, or `failed` when it
+/// stopped with an error, which is passed on.
+fn finish_run(store: &Store, run_id: i64, outcome: Result<usize>) -> Result<()> {
+    match outcome {
+        Ok(failed) => store.finish_run(run_id, if failed == 0 { "ok" } else { "partial" }),
+        Err(e) => {
+            if let Err(mark) = store.finish_run(run_id, "failed") {
+                warn!("Marking run {run_id} as failed: {mark:#}");
+            }
+            Err(e)
+        }
+    }
+}
 
-        html.push_str(&format!(
-            "<h2><a href=\"{url}\">{title}</a></h2>",
-            url = item_data.url,
-            title = item_data.title
-        ));
-        if !item_data.summary.is_empty() {
-            html.push_str(&format!(
-                "<p>{}</p>",
-                item_data.summary.replace('\n', "<br>")
-            ));
-        }
-
-        let pub_date = item_data.created_at.format(&Rfc2822)?;
-        let item = ItemBuilder::default()
-            .title(item_data.title.clone())
-            .link(item_data.url.clone())
-            .author(Some(item_data.author.clone()))
-            .description((!item_data.summary.is_empty()).then_some(item_data.summary.clone()))
-            .pub_date(pub_date)
-            .build();
-        items.push(item);
-    }
-
-    html.push_str("</body></html>");
-    std::fs::create_dir_all("public")?;
-    std::fs::write("public/index.html", html)?;
-
-    let channel = ChannelBuilder::default()
-        .title(format!(
-            "Zcash Forum Digest for {}",
-            OffsetDateTime::now_utc().date()
-        ))
-        .link("https://forum.zcashcommunity.com")
-        .description("Topics updated in the last 24 hours")
-        .items(items)
-        .build();
-    std::fs::write("public/rss.xml", channel.to_string())?;
-    Ok(())
+/// Summarize the stored topics of every forum and store the digest items.
+///
+/// A topic whose summary fails or times out keeps its digest item with an
+/// empty summary and is recorded in `report` and the store, so `render` can
+/// leave it out or flag it.
+async fn summarize(
+    summarizer: &dyn Summarizer,
+    config: &Config,
+    store: &mut Store,
+    report: &mut RunReport,
+) -> Result<()> {
+    let run_id = store.begin_run("summarize", config.digest.cutoff_hours, summarizer.model())?;
+    let outcome = summarize_forums(summarizer, config, store, report, run_id).await;
+    finish_run(store, run_id, outcome)
 }
 
-async fn fetch_latest(client: &Client) -> Result<Latest> {
-    Ok(client
-        .get("https://forum.zcashcommunity.com/latest.json")
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<Latest>()
-        .await?)
-}
-
-async fn fetch_topic_page(client: &Client, id: u64, page: u32) -> Result<TopicFull> {
-    let url = if page == 0 {
-        format!("https://forum.zcashcommunity.com/t/{}.json", id)
-    } else {
-        format!(
-            "https://forum.zcashcommunity.com/t/{}.json?page={}",
-            id, page
-        )
+/// Body of [`summarize`]; returns the number of failures recorded.
+async fn summarize_forums(
+    summarizer: &dyn Summarizer,
+    config: &Config,
+    store: &mut Store,
+    report: &mut RunReport,
+    run_id: i64,
+) -> Result<usize> {
+    let system = config.system_prompt();
+    match (system, &config.summarize.system_prompt) {
+        (None, _) => info!("Using the model's built-in system prompt"),
+        (Some(_), Some(_)) => info!("Using the configured system prompt"),
+        (Some(_), None) => info!("Using system prompt v{SYSTEM_PROMPT_VERSION}"),
+    }
+
+    // Warmup
+    let warm_prompt = build_prompt("warmup", "warmup");
+    let warmup = Request {
+        kind: PromptKind::Warmup,
+        system,
+        prompt: &warm_prompt,
+        format: None,
+        deadline: None,
     };
-    Ok(client
-        .get(&url)
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<TopicFull>()
-        .await?)
-}
-
-async fn fetch_posts(client: &Client, id: u64, cutoff: OffsetDateTime) -> Result<Vec<Post>> {
-    let mut all = Vec::new();
-    let mut page = 0;
-    loop {
-        match fetch_topic_page(client, id, page).await {
-            Ok(tf) => {
-                let count = tf.post_stream.posts.len();
-                if count == 0 {
-                    break;
-                }
-                all.extend(
-                    tf.post_stream
-                        .posts
-                        .into_iter()
-                        .filter(|p| p.created_at >= cutoff),
+    if let Err(e) = summarizer.summarize(warmup).await {
+        warn!("Warm-up summarize failed: {e}");
+    }
+
+    let cutoff = cutoff(config);
+    let limit = Semaphore::new(config.summarize.concurrency);
+    let (mut hits, mut misses, mut failed) = (0usize, 0usize, 0usize);
+    for forum in &config.forums {
+        let mut jobs = Vec::new();
+        for topic in store.topics_active_since(&forum.base_url, cutoff)? {
+            // Topics stored before the filter changed are not summarized.
+            if !forum
+                .filter
+                .allows(topic.id, topic.category.as_deref(), &topic.tags)
+            {
+                continue;
+            }
+            let posts = store.posts_since(&forum.base_url, topic.id, cutoff)?;
+            let Some(last_post) = posts.iter().max_by_key(|p| p.created_at).cloned() else {
+                continue;
+            };
+            // Posts are oldest first; past the cap, the newest are kept.
+            let skip = posts
+                .len()
+                .saturating_sub(config.summarize.max_posts_for_chunk);
+            let all =
+                posts_to_token_chunks(posts.iter().skip(skip), chunk_budget(config, &topic.title));
+            let total = all.len();
+            let chunks = within_budget(all, config.token_budget());
+            if chunks.len() < total {
+                warn!(
+                    "Topic {}: token budget reached, dropped the {} oldest of {total} chunks after the first",
+                    topic.id,
+                    total - chunks.len()
                 );
-                if count < PAGE_SIZE {
-                    break;
-                }
-                page += 1;
-                sleep(StdDuration::from_secs(1)).await;
             }
-            Err(e) => {
-                if let Some(req_err) = e.downcast_ref::<reqwest::Error>() {
-                    if req_err.status() == Some(StatusCode::NOT_FOUND) {
-                        break;
+            jobs.push(SummaryJob {
+                topic,
+                last_post,
+                chunks,
+            });
+        }
+
+        // Topics go to the LLM concurrently, each call answered from the
+        // cache when it can be; results keep topic order.
+        let cache = &*store;
+        let outcomes = join_all(jobs.iter().map(|job| async {
+            if job.chunks.is_empty() {
+                return Ok(ThreadSummary::default());
+            }
+            let _permit = limit.acquire().await.expect("semaphore is never closed");
+            summarize_thread_cached(
+                summarizer,
+                config,
+                Some(cache),
+                &job.topic.title,
+                &job.chunks,
+            )
+            .await
+            .map_err(|e| format!("{e:#}"))
+        }))
+        .await;
+
+        let mut items = Vec::with_capacity(jobs.len());
+        for (job, outcome) in jobs.into_iter().zip(outcomes) {
+            if let Ok(out) = &outcome {
+                report.usage.merge(&out.usage);
+                hits += out.cache_hits;
+                misses += out.usage.calls;
+            }
+            let (summary, structured) = match outcome {
+                Ok(out) => {
+                    if out.truncated {
+                        // Kept for this digest, but not cached for the next one.
+                        warn!("Summary of topic {} is truncated", job.topic.id);
+                        report.topics_truncated += 1;
                     }
+                    report.topics_summarized += 1;
+                    // Structured summaries come with their fields, which for
+                    // a salvaged reply may be fewer than `parse` requires.
+                    match out.structured {
+                        Some(s) => (s.to_plain_text(), Some(s)),
+                        None => (strip_post_tags(&out.raw), None),
+                    }
+                }
+                Err(reason) => {
+                    warn!("LLM summarize failed for {}: {reason}", job.topic.id);
+                    let failure = Failure {
+                        forum: forum.base_url.clone(),
+                        topic_id: Some(job.topic.id),
+                        title: Some(job.topic.title.clone()),
+                        stage: Stage::Summarize,
+                        reason,
+                    };
+                    store.record_failure(run_id, &failure)?;
+                    report.failures.push(failure);
+                    failed += 1;
+                    (String::new(), None)
                 }
-                return Err(e);
+            };
+            let mut item = compose_digest_item(
+                &forum.base_url,
+                job.topic.id,
+                &job.topic.title,
+                &job.last_post,
+                summary,
+            );
+            item.structured = structured;
+            items.push(item);
+        }
+        store.save_digest_items(run_id, &forum.slug(), &items)?;
+    }
+    info!("Summary cache: {hits} hits, {misses} misses");
+    let usage = &report.usage;
+    info!(
+        "LLM usage: {} calls, {} prompt + {} completion tokens, {:.1}s total ({:.1}s loading)",
+        usage.calls,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.total_secs,
+        usage.load_secs
+    );
+    if let Some(rate) = usage.tokens_per_sec {
+        info!("Generation speed: {rate:.1} tokens/s");
+    }
+    Ok(failed)
+}
+
+/// A stored topic ready to be summarized.
+struct SummaryJob {
+    topic: StoredTopic,
+    last_post: Post,
+    /// Empty when there is nothing to summarize.
+    chunks: Vec<String>,
+}
+
+/// Render HTML and RSS from the digest items of the latest summarize run.
+fn render(config: &Config, store: &Store) -> Result<()> {
+    let Some(run) = store.latest_run("summarize")? else {
+        bail!("no summaries in the store yet; run `summarize` first");
+    };
+    let date = OffsetDateTime::now_utc().date();
+    let description = format!(
+        "Topics updated in the last {} hours",
+        config.digest.cutoff_hours
+    );
+
+    let failures = store.failures(run.id)?;
+    let mut sections = Vec::with_capacity(config.forums.len());
+    for forum in &config.forums {
+        let mut items = store.digest_items(run.id, &forum.slug())?;
+        let failed: HashSet<u64> = failures
+            .iter()
+            .filter(|f| f.forum == forum.base_url)
+            .filter_map(|f| f.topic_id)
+            .collect();
+        let failed = if config.digest.render_failures {
+            failed
+        } else {
+            items.retain(|item| !failed.contains(&item.topic_id));
+            HashSet::new()
+        };
+        let dir = config
+            .digest
+            .merge_dir
+            .as_ref()
+            .unwrap_or(&forum.output_dir);
+        if !forum.publish_private {
+            let private = store.private_topics(&forum.base_url)?;
+            let n = items
+                .iter()
+                .filter(|item| private.contains(&item.topic_id))
+                .count();
+            if n > 0 {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("creating {}", dir.display()))?;
+                let public = is_public_dir(dir).with_context(|| {
+                    format!(
+                        "refusing to render {n} topics from private categories of {}: cannot tell whether {} is public",
+                        forum.name,
+                        dir.display()
+                    )
+                })?;
+                if public {
+                    bail!(
+                        "refusing to publish {n} topics from private categories of {} into {}; write that forum's digest outside `{PUBLIC_DIR}/` or set `publish_private = true` on it",
+                        forum.name,
+                        dir.display()
+                    );
+                }
+            }
+        }
+        let section = ForumDigest {
+            forum,
+            items,
+            failed,
+        };
+        if config.digest.merge_dir.is_none() {
+            write_digest(
+                &forum.output_dir,
+                &format!("{} Digest for {date}", forum.name),
+                &forum.base_url,
+                &description,
+                std::slice::from_ref(&section),
+            )?;
+        }
+        sections.push(section);
+    }
+
+    if let Some(dir) = &config.digest.merge_dir {
+        write_digest(
+            dir,
+            &format!("{} for {date}", config.digest.merged_title),
+            &config.forums[0].base_url,
+            &description,
+            &sections,
+        )?;
+    }
+    Ok(())
+}
+
+/// Topics of `forum` from `/latest.json`, or from the listings of the
+/// categories and tags its filter allows when `filter.use_listings` is set,
+/// most recently active first.
+async fn list_topics(
+    client: &DiscourseClient,
+    forum: &Forum,
+    categories: &[Category],
+    cutoff: OffsetDateTime,
+    max_pages: usize,
+) -> Result<Vec<TopicStub>> {
+    let filter = &forum.filter;
+    for slug in filter.categories.iter().chain(&filter.exclude_categories) {
+        if !categories.iter().any(|c| &c.slug == slug) {
+            warn!("{} has no category `{slug}`", forum.name);
+        }
+    }
+    if !filter.use_listings {
+        return client.latest_since(cutoff, max_pages).await;
+    }
+
+    let mut listings = Vec::new();
+    for slug in &filter.categories {
+        let Some(category) = categories.iter().find(|c| &c.slug == slug) else {
+            bail!("no category `{slug}` to list on {}", forum.name);
+        };
+        listings.push(Listing::Category(category));
+    }
+    listings.extend(filter.tags.iter().map(|t| Listing::Tag(t)));
+    let mut seen = HashSet::new();
+    let mut topics = Vec::new();
+    for listing in &listings {
+        for t in client.listing_since(listing, cutoff, max_pages).await? {
+            if seen.insert(t.id) {
+                topics.push(t);
             }
         }
     }
-    Ok(all)
+    topics.sort_by_key(|t| std::cmp::Reverse(t.last_activity()));
+    Ok(topics)
 }
 
-fn build_prompt(topic_title: &str, chunk: &str) -> String {
-    format!(
-        "Thread: {title}\n\nContent excerpt:\n---\n{body}\n---",
-        title = topic_title,
-        body = chunk
-    )
+/// New posts of a topic, to be written by the caller along with the cutoff
+/// they were synced for.
+struct TopicUpdate {
+    posts: Vec<Post>,
+    high_water: u64,
+}
+
+/// Work out what is needed to bring a topic's stored posts up to date.
+///
+/// Topics whose `highest_post_number` has not moved past the stored mark are
+/// skipped without any request, unless their last sync stopped at a later
+/// cutoff than `cutoff`. Otherwise the post id stream is read from
+/// `/t/{id}.json` and only ids not yet stored are requested, newest first,
+/// stopping at the first batch that lies entirely before `cutoff`. Every post
+/// fetched is kept, older ones included. Edits to already stored posts are
+/// not picked up.
+async fn sync_topic(
+    client: &DiscourseClient,
+    store: &Store,
+    forum: &Forum,
+    stub: &TopicStub,
+    cutoff: OffsetDateTime,
+    batch_size: usize,
+) -> Result<Option<TopicUpdate>> {
+    let base = &forum.base_url;
+    let high_water = store.high_water(base, stub.id)?;
+    // Posts before the cutoff of the last sync may never have been fetched,
+    // so a wider window syncs the topic again.
+    let covered = store
+        .synced_since(base, stub.id)?
+        .is_some_and(|since| since <= cutoff);
+    if covered && stub.highest_post_number.is_some_and(|n| n <= high_water) {
+        return Ok(None);
+    }
+
+    let topic = match client.topic(stub.id).await {
+        Ok(t) => t,
+        Err(e) if is_status(&e, StatusCode::NOT_FOUND) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let known = store.known_post_ids(base, stub.id)?;
+    let mut new_posts: Vec<Post> = Vec::new();
+    let mut max_number = high_water.max(stub.highest_post_number.unwrap_or(0));
+    for p in topic.post_stream.posts {
+        max_number = max_number.max(p.post_number);
+        if !known.contains(&p.id) {
+            new_posts.push(p);
+        }
+    }
+
+    let fetched: HashSet<u64> = new_posts.iter().map(|p| p.id).collect();
+    let missing: Vec<u64> = topic
+        .post_stream
+        .stream
+        .iter()
+        .rev()
+        .copied()
+        .filter(|id| !known.contains(id) && !fetched.contains(id))
+        .collect();
+    for batch in missing.chunks(batch_size) {
+        let posts = client.posts(stub.id, batch).await?;
+        let any_recent = posts.iter().any(|p| p.created_at >= cutoff);
+        for p in posts {
+            max_number = max_number.max(p.post_number);
+            new_posts.push(p);
+        }
+        if !any_recent {
+            break;
+        }
+    }
+
+    info!(
+        "Topic {}: {} new posts (high-water {} -> {})",
+        stub.id,
+        new_posts.len(),
+        high_water,
+        max_number
+    );
+    Ok(Some(TopicUpdate {
+        posts: new_posts,
+        high_water: max_number,
+    }))
 }
@@ -0,0 +1,297 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use tokio::time::{Instant, timeout};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::prompt::{
+    PROMPT_TEMPLATE, PromptKind, REDUCE_TEMPLATE, build_prompt, build_reduce_prompt,
+};
+use crate::store::Store;
+use crate::structured::{SCHEMA, StructuredSummary, summarize_structured};
+use crate::summarizer::{Request, Summarizer, SummaryResult, Usage, count_tokens};
+use crate::{strip_post_tags, summary_cache_key};
+
+/// Final summary of a thread.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThreadSummary {
+    /// Raw output of the final call; structured summaries come back as
+    /// validated JSON.
+    pub raw: String,
+    /// Fields of a structured summary as accepted, which for a reply cut off
+    /// mid-JSON may be fewer than [`StructuredSummary::parse`] requires.
+    pub structured: Option<StructuredSummary>,
+    /// Some call was cut off, so the summary may miss part of the thread.
+    pub truncated: bool,
+    /// Summed over every call made for the thread.
+    pub usage: Usage,
+    /// Calls answered from the summary cache instead of the model.
+    pub cache_hits: usize,
+}
+
+/// Tokens of thread text that fit in one prompt for a topic titled `title`.
+pub fn chunk_budget(config: &Config, title: &str) -> usize {
+    config
+        .chunk_tokens()
+        .saturating_sub(count_tokens(title))
+        .max(1)
+}
+
+/// Keep the first chunk and as many of the newest chunks as fit within
+/// `budget` tokens alongside it, in thread order. The chunks dropped are the
+/// oldest ones after the first, so the start of the excerpt and its latest
+/// activity both make it into the summary.
+pub fn within_budget(chunks: Vec<String>, budget: usize) -> Vec<String> {
+    let mut chunks = chunks.into_iter();
+    let Some(first) = chunks.next() else {
+        return Vec::new();
+    };
+    let rest: Vec<String> = chunks.collect();
+    let mut used = count_tokens(&first);
+    let mut start = rest.len();
+    while start > 0 {
+        let tokens = count_tokens(&rest[start - 1]);
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        start -= 1;
+    }
+    let mut kept = Vec::with_capacity(1 + rest.len() - start);
+    kept.push(first);
+    kept.extend(rest.into_iter().skip(start));
+    kept
+}
+
+/// Summarize a thread split into `chunks`.
+///
+/// A single chunk is summarized directly. Otherwise every chunk is summarized
+/// on its own, then consecutive partial summaries are combined in groups that
+/// fit [`chunk_budget`] until one summary is left. At level
+/// `summarize.max_depth` everything left is combined in one call. Each call is
+/// bounded by `summarize.timeout_secs`; streaming backends hand back what they
+/// generated by then, which marks the summary as truncated.
+pub async fn summarize_thread(
+    summarizer: &dyn Summarizer,
+    config: &Config,
+    title: &str,
+    chunks: &[String],
+) -> Result<ThreadSummary> {
+    summarize_thread_cached(summarizer, config, None, title, chunks).await
+}
+
+/// Like [`summarize_thread`], but every call is first looked up in `cache`
+/// and its output stored there unless it was cut off. Each chunk summary is
+/// keyed on its own prompt and each combining call on the summaries it
+/// combines, so a new post only costs the calls whose input changed.
+pub async fn summarize_thread_cached(
+    summarizer: &dyn Summarizer,
+    config: &Config,
+    cache: Option<&Store>,
+    title: &str,
+    chunks: &[String],
+) -> Result<ThreadSummary> {
+    if chunks.is_empty() {
+        bail!("nothing to summarize");
+    }
+    let mut truncated = false;
+    let mut usage = Usage::default();
+    let mut cache_hits = 0;
+    let mut summaries = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        let prompt = build_prompt(title, chunk);
+        let out = call(summarizer, config, cache, PromptKind::Summary, &prompt).await?;
+        match out.result {
+            Some(r) => {
+                truncated |= r.truncated;
+                usage.add(&r);
+            }
+            None => cache_hits += 1,
+        }
+        if chunks.len() == 1 {
+            return Ok(ThreadSummary {
+                raw: out.raw,
+                structured: out.structured,
+                truncated,
+                usage,
+                cache_hits,
+            });
+        }
+        summaries.push(out.text);
+    }
+
+    for depth in 1.. {
+        let groups = if depth >= config.summarize.max_depth {
+            vec![summaries]
+        } else {
+            group(summaries, chunk_budget(config, title))
+        };
+        debug!(
+            "Reducing {title:?} at level {depth}: {} group(s)",
+            groups.len()
+        );
+        let last = groups.len() == 1;
+        let mut next = Vec::with_capacity(groups.len());
+        for g in groups {
+            let prompt = build_reduce_prompt(title, &g.join("\n\n"));
+            let out = call(summarizer, config, cache, PromptKind::Reduce, &prompt).await?;
+            match out.result {
+                Some(r) => {
+                    truncated |= r.truncated;
+                    usage.add(&r);
+                }
+                None => cache_hits += 1,
+            }
+            if last {
+                return Ok(ThreadSummary {
+                    raw: out.raw,
+                    structured: out.structured,
+                    truncated,
+                    usage,
+                    cache_hits,
+                });
+            }
+            next.push(out.text);
+        }
+        summaries = next;
+    }
+    unreachable!("every level at least halves the summaries")
+}
+
+/// Split summaries into consecutive groups of at most `max_tokens` tokens.
+/// Groups hold at least two summaries so that every level makes progress.
+fn group(summaries: Vec<String>, max_tokens: usize) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut size = 0usize;
+    for s in summaries {
+        // Plus the blank line joining summaries.
+        let len = count_tokens(&s) + 1;
+        match groups.last_mut() {
+            Some(g) if g.len() < 2 || size + len <= max_tokens => {
+                size += len;
+                g.push(s);
+            }
+            _ => {
+                size = len;
+                groups.push(vec![s]);
+            }
+        }
+    }
+    if groups.len() > 1 && groups.last().is_some_and(|g| g.len() == 1) {
+        let tail = groups.pop().expect("checked above");
+        groups.last_mut().expect("checked above").extend(tail);
+    }
+    groups
+}
+
+/// Output of one LLM call.
+struct CallOutput {
+    raw: String,
+    /// Plain-text form, fed to the next reduce level.
+    text: String,
+    structured: Option<StructuredSummary>,
+    /// `None` when the output came from the cache.
+    result: Option<SummaryResult>,
+}
+
+/// Extra time past the deadline before a call is abandoned, for streaming
+/// backends to hand back their partial output.
+const DEADLINE_GRACE: Duration = Duration::from_secs(5);
+
+/// Output of one call: from `cache` when it holds the output of the same
+/// prompt, otherwise from the model and then stored in `cache`.
+async fn call(
+    summarizer: &dyn Summarizer,
+    config: &Config,
+    cache: Option<&Store>,
+    kind: PromptKind,
+    prompt: &str,
+) -> Result<CallOutput> {
+    let Some(cache) = cache else {
+        return call_model(summarizer, config, kind, prompt).await;
+    };
+    let mut template = match kind {
+        PromptKind::Reduce => REDUCE_TEMPLATE,
+        _ => PROMPT_TEMPLATE,
+    }
+    .to_string();
+    if config.summarize.structured {
+        template = format!("{template}\n{}", *SCHEMA);
+    }
+    let model = summarizer.model();
+    let key = summary_cache_key(model, config.system_prompt(), &template, prompt);
+    if let Some(raw) = cache.cached_summary(&key)? {
+        // Entries are checked when stored, so one that no longer parses is
+        // from an older schema and is made again.
+        if let Some(out) = cached_output(config, raw) {
+            return Ok(out);
+        }
+    }
+    let out = call_model(summarizer, config, kind, prompt).await?;
+    if out.result.as_ref().is_some_and(|r| !r.truncated) {
+        cache.cache_summary(&key, model, &out.raw)?;
+    }
+    Ok(out)
+}
+
+fn cached_output(config: &Config, raw: String) -> Option<CallOutput> {
+    if !config.summarize.structured {
+        return Some(CallOutput {
+            text: strip_post_tags(&raw),
+            raw,
+            structured: None,
+            result: None,
+        });
+    }
+    let s = StructuredSummary::parse(&raw).ok()?;
+    Some(CallOutput {
+        raw,
+        text: s.to_plain_text(),
+        structured: Some(s),
+        result: None,
+    })
+}
+
+/// One LLM call, bounded by `summarize.timeout_secs`.
+async fn call_model(
+    summarizer: &dyn Summarizer,
+    config: &Config,
+    kind: PromptKind,
+    prompt: &str,
+) -> Result<CallOutput> {
+    let secs = config.summarize.timeout_secs;
+    let limit = Duration::from_secs(secs);
+    let req = Request {
+        kind,
+        system: config.system_prompt(),
+        prompt,
+        format: None,
+        deadline: Some(Instant::now() + limit),
+    };
+    let call = async {
+        if config.summarize.structured {
+            let (s, r) = summarize_structured(summarizer, req).await?;
+            Ok(CallOutput {
+                raw: serde_json::to_string(&s)?,
+                text: s.to_plain_text(),
+                structured: Some(s),
+                result: Some(r),
+            })
+        } else {
+            let r = summarizer.summarize(req).await?;
+            if r.truncated && r.text.trim().is_empty() {
+                bail!("timed out after {secs}s");
+            }
+            Ok(CallOutput {
+                text: strip_post_tags(&r.text),
+                raw: r.text.clone(),
+                structured: None,
+                result: Some(r),
+            })
+        }
+    };
+    timeout(limit + DEADLINE_GRACE, call)
+        .await
+        .map_err(|_| anyhow!("timed out after {secs}s"))?
+}
@@ -1,30 +1,115 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
-use anyhow::{Result, anyhow};
-use backoff::{ExponentialBackoff, future::retry};
+use anyhow::{Context, Result, bail};
+use futures::future::BoxFuture;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::{Instant, timeout_at};
+use tracing::{info, warn};
 
-use crate::BPE;
+use crate::prompt::{PromptKind, SYSTEM_PROMPT};
+use crate::summarizer::{
+    Request, Summarizer, SummaryResult, Timings, count_tokens, get_json, post_json, send_streaming,
+};
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize)]
 struct Msg<'a> {
     role: &'a str,
     content: &'a str,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize)]
 struct ChatReq<'a> {
     model: &'a str,
     stream: bool,
     messages: Vec<Msg<'a>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     keep_alive: Option<&'a str>,
+    #[serde(skip_serializing_if = "Options::is_empty")]
+    options: &'a Options,
+    /// JSON schema constraining the reply.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'a serde_json::Value>,
+}
+
+/// Generation options sent with each `/api/chat` request. Unset fields fall
+/// back to the model's defaults (e.g. `PARAMETER` lines in the `Modelfile`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+}
+
+impl Options {
+    pub fn is_empty(&self) -> bool {
+        *self == Options::default()
+    }
+
+    /// `self` with every field set in `other` replaced by `other`'s value.
+    pub fn merged(&self, other: &Options) -> Options {
+        Options {
+            temperature: other.temperature.or(self.temperature),
+            num_ctx: other.num_ctx.or(self.num_ctx),
+            top_p: other.top_p.or(self.top_p),
+            num_predict: other.num_predict.or(self.num_predict),
+            seed: other.seed.or(self.seed),
+            repeat_penalty: other.repeat_penalty.or(self.repeat_penalty),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct ChatResp {
     message: ChatMsg,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+/// Counts and timings (in nanoseconds) Ollama sends with a finished reply.
+#[derive(Deserialize, Default)]
+struct Stats {
+    #[serde(default)]
+    prompt_eval_count: Option<usize>,
+    #[serde(default)]
+    eval_count: Option<usize>,
+    #[serde(default)]
+    total_duration: Option<u64>,
+    #[serde(default)]
+    load_duration: Option<u64>,
+    #[serde(default)]
+    eval_duration: Option<u64>,
+}
+
+impl Stats {
+    /// The result for `text`, falling back to cl100k estimates for counts
+    /// Ollama did not send; `prompt_estimate` is the estimate for the prompt.
+    fn result(self, text: String, prompt_estimate: usize, truncated: bool) -> SummaryResult {
+        let timings = self.total_duration.map(|total| Timings {
+            total: Duration::from_nanos(total),
+            load: Duration::from_nanos(self.load_duration.unwrap_or_default()),
+            eval: Duration::from_nanos(self.eval_duration.unwrap_or_default()),
+        });
+        SummaryResult {
+            prompt_tokens: self.prompt_eval_count.unwrap_or(prompt_estimate),
+            completion_tokens: self.eval_count.unwrap_or_else(|| count_tokens(&text)),
+            text,
+            truncated,
+            timings,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -32,76 +117,559 @@ struct ChatMsg {
     content: String,
 }
 
-pub async fn summarize_with_ollama(
+/// One NDJSON line of a streamed `/api/chat` response.
+#[derive(Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    message: Option<ChatMsg>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+    /// Set on the final line only.
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+/// How often a streamed generation logs its progress.
+const PROGRESS_EVERY: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct Tags {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct ShowReq<'a> {
+    model: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ShowResp {
+    /// `PARAMETER` lines, one `name value` pair per line.
+    #[serde(default)]
+    parameters: String,
+    #[serde(default)]
+    model_info: HashMap<String, serde_json::Value>,
+}
+
+/// What `/api/show` reports about an installed model.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelInfo {
+    /// Context length the model was trained with (`<arch>.context_length`).
+    pub context_length: Option<usize>,
+    /// `PARAMETER` lines as `(name, value)` pairs, e.g. `("num_ctx", "8192")`.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ModelInfo {
+    /// `num_ctx` set by the model's `Modelfile`.
+    pub fn num_ctx(&self) -> Option<usize> {
+        self.parameters
+            .iter()
+            .find(|(name, _)| name == "num_ctx")
+            .and_then(|(_, value)| value.parse().ok())
+    }
+}
+
+impl From<ShowResp> for ModelInfo {
+    fn from(r: ShowResp) -> Self {
+        let arch = r
+            .model_info
+            .get("general.architecture")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let context_length = r
+            .model_info
+            .get(&format!("{arch}.context_length"))
+            .and_then(serde_json::Value::as_u64)
+            .map(|n| n as usize);
+        let parameters = r
+            .parameters
+            .lines()
+            .filter_map(|line| {
+                let (name, value) = line.trim().split_once(char::is_whitespace)?;
+                Some((name.to_string(), value.trim().to_string()))
+            })
+            .collect();
+        ModelInfo {
+            context_length,
+            parameters,
+        }
+    }
+}
+
+/// Whether `model` is installed on the Ollama server at `base`, per
+/// `/api/tags`. A model named without a tag matches `:latest`.
+pub async fn has_model(
     client: &Client,
     base: &str,
     model: &str,
-    prompt: &str,
-) -> Result<(String, usize, usize)> {
-    let url = format!("{}/api/chat", base.trim_end_matches('/'));
+    max_elapsed: Duration,
+) -> Result<bool> {
+    let url = format!("{}/api/tags", base.trim_end_matches('/'));
+    let tags: Tags = get_json(client, &url, max_elapsed)
+        .await
+        .with_context(|| format!("listing models at {url}"))?;
+    Ok(tags
+        .models
+        .iter()
+        .any(|t| t.name == model || (!model.contains(':') && t.name == format!("{model}:latest"))))
+}
 
-    /* Note: System prompt is set in the Modelfile */
-    let body = ChatReq {
-        model,
-        stream: false,
-        keep_alive: Some("5m"),
-        messages: vec![Msg {
-            role: "user",
-            content: prompt,
-        }],
-    };
+/// Read the context length and parameters of an installed model from
+/// `/api/show`.
+pub async fn show_model(
+    client: &Client,
+    base: &str,
+    model: &str,
+    max_elapsed: Duration,
+) -> Result<ModelInfo> {
+    let url = format!("{}/api/show", base.trim_end_matches('/'));
+    let r: ShowResp = post_json(client, &url, None, &ShowReq { model }, max_elapsed)
+        .await
+        .with_context(|| format!("reading {model} details from {url}"))?;
+    Ok(r.into())
+}
 
-    let in_tok: usize = body
-        .messages
-        .iter()
-        .map(|m| BPE.encode_with_special_tokens(m.content).len())
-        .sum();
+/// Upper bound on a model download or build; the client's default request
+/// timeout is far too short for multi-gigabyte pulls.
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
 
-    let url_clone = url.clone();
-    let body_clone = body.clone();
+/// The parts of a `Modelfile` that `/api/create` accepts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Modelfile {
+    /// Base model (`FROM`).
+    pub from: String,
+    pub system: Option<String>,
+    pub template: Option<String>,
+    /// `PARAMETER` lines in file order; `stop` may repeat.
+    pub parameters: Vec<(String, String)>,
+}
 
-    let max_elapsed = std::env::var("OLLAMA_MAX_ELAPSED_SECS")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok())
-        .map(Duration::from_secs)
-        .unwrap_or(Duration::from_secs(120));
+impl Modelfile {
+    /// Parse `FROM`, `SYSTEM`, `TEMPLATE` and `PARAMETER` instructions.
+    /// Values may be quoted or span lines in `"""` blocks; other
+    /// instructions are rejected.
+    pub fn parse(text: &str) -> Result<Modelfile> {
+        let mut m = Modelfile::default();
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            match cmd.to_ascii_uppercase().as_str() {
+                "FROM" => m.from = rest.to_string(),
+                "SYSTEM" => m.system = Some(block_value(rest, &mut lines)?),
+                "TEMPLATE" => m.template = Some(block_value(rest, &mut lines)?),
+                "PARAMETER" => {
+                    let Some((name, value)) = rest.split_once(char::is_whitespace) else {
+                        bail!("Modelfile: PARAMETER {rest:?} has no value");
+                    };
+                    let value = value.trim();
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value);
+                    m.parameters.push((name.to_string(), value.to_string()));
+                }
+                other => bail!("Modelfile: unsupported instruction {other}"),
+            }
+        }
+        if m.from.is_empty() {
+            bail!("Modelfile: missing FROM");
+        }
+        Ok(m)
+    }
 
-    let backoff = ExponentialBackoff {
-        max_elapsed_time: Some(max_elapsed),
-        ..Default::default()
+    /// `/api/create` body for a model named `model`.
+    fn create_request(&self, model: &str) -> serde_json::Value {
+        let mut parameters = serde_json::Map::new();
+        for (name, value) in &self.parameters {
+            let value = match value.parse::<i64>() {
+                Ok(n) => n.into(),
+                Err(_) => value
+                    .parse::<f64>()
+                    .map_or_else(|_| value.as_str().into(), Into::into),
+            };
+            if name == "stop" {
+                let stops = parameters.entry(name).or_insert_with(|| json!([]));
+                if let Some(stops) = stops.as_array_mut() {
+                    stops.push(value);
+                }
+            } else {
+                parameters.insert(name.clone(), value);
+            }
+        }
+        let mut body = json!({"model": model, "from": self.from, "stream": true});
+        if let Some(system) = &self.system {
+            body["system"] = system.as_str().into();
+        }
+        if let Some(template) = &self.template {
+            body["template"] = template.as_str().into();
+        }
+        if !parameters.is_empty() {
+            body["parameters"] = parameters.into();
+        }
+        body
+    }
+}
+
+/// Value of a `SYSTEM` or `TEMPLATE` instruction: a `"""` block that may
+/// span lines, a quoted string, or the rest of the line.
+fn block_value<'a>(rest: &str, lines: &mut impl Iterator<Item = &'a str>) -> Result<String> {
+    let Some(open) = rest.strip_prefix(r#"""""#) else {
+        let v = rest.strip_prefix('"').and_then(|v| v.strip_suffix('"'));
+        return Ok(v.unwrap_or(rest).to_string());
+    };
+    if let Some(v) = open.strip_suffix(r#"""""#) {
+        return Ok(v.to_string());
+    }
+    let mut value = open.to_string();
+    for line in lines.by_ref() {
+        if let Some(last) = line.strip_suffix(r#"""""#) {
+            value.push('\n');
+            value.push_str(last);
+            return Ok(value.trim_matches('\n').to_string());
+        }
+        value.push('\n');
+        value.push_str(line);
+    }
+    bail!(r#"Modelfile: unterminated """ block"#)
+}
+
+/// One line of `/api/pull` or `/api/create` progress.
+#[derive(Deserialize)]
+struct Progress {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    total: Option<u64>,
+    #[serde(default)]
+    completed: Option<u64>,
+}
+
+/// Install a missing model.
+///
+/// With a `modelfile` whose base is not `model` itself, `model` is built
+/// with `/api/create`, pulling the base first if it is missing too. Otherwise
+/// `model` is treated as a registry tag and pulled with `/api/pull`. Progress
+/// is logged as it streams in.
+pub async fn bootstrap(
+    client: &Client,
+    base: &str,
+    model: &str,
+    modelfile: Option<&Modelfile>,
+    max_elapsed: Duration,
+) -> Result<()> {
+    let base = base.trim_end_matches('/');
+    let from = modelfile.filter(|m| m.from != model);
+    let Some(modelfile) = from else {
+        info!("Pulling {model}");
+        return stream_progress(
+            client,
+            &format!("{base}/api/pull"),
+            &json!({"model": model}),
+        )
+        .await;
     };
-    let op = move || {
-        let url = url_clone.clone();
-        let body = body_clone.clone();
-        async move {
-            let resp = client
-                .post(&url)
-                .json(&body)
-                .send()
-                .await
-                .map_err(|e| backoff::Error::transient(anyhow!("transport: {e:?}")))?;
-
-            let status = resp.status();
-            if status.is_client_error() {
-                let text = resp.text().await.unwrap_or_default();
-                return Err(backoff::Error::permanent(anyhow!("http {status}: {text}")));
-            } else if !status.is_success() {
-                let text = resp.text().await.unwrap_or_default();
-                return Err(backoff::Error::transient(anyhow!("http {status}: {text}")));
+    if !has_model(client, base, &modelfile.from, max_elapsed).await? {
+        info!("Pulling {}, the base of {model}", modelfile.from);
+        let body = json!({"model": modelfile.from});
+        stream_progress(client, &format!("{base}/api/pull"), &body).await?;
+    }
+    info!("Creating {model} from its Modelfile");
+    let body = modelfile.create_request(model);
+    stream_progress(client, &format!("{base}/api/create"), &body).await
+}
+
+/// POST to a streaming endpoint and log each status line until `success`.
+async fn stream_progress(client: &Client, url: &str, body: &serde_json::Value) -> Result<()> {
+    let mut resp = client
+        .post(url)
+        .timeout(BOOTSTRAP_TIMEOUT)
+        .json(body)
+        .send()
+        .await
+        .with_context(|| format!("POST {url}"))?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        bail!("{url}: http {status}: {text}");
+    }
+
+    let mut buf = Vec::new();
+    let mut last: Option<(String, u64)> = None;
+    let mut done = false;
+    let mut handle = |line: &[u8]| -> Result<()> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let p: Progress = serde_json::from_slice(line).context("undecodable progress line")?;
+        if let Some(err) = p.error {
+            bail!("{url}: {err}");
+        }
+        // Log each status once, and downloads every 10%.
+        let decile = match (p.completed, p.total) {
+            (Some(c), Some(t)) if t > 0 => c * 10 / t,
+            _ => 0,
+        };
+        if last.as_ref() != Some(&(p.status.clone(), decile)) {
+            if decile > 0 {
+                info!("{}: {}%", p.status, decile * 10);
+            } else {
+                info!("{}", p.status);
             }
+            last = Some((p.status.clone(), decile));
+        }
+        done |= p.status == "success";
+        Ok(())
+    };
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .with_context(|| format!("reading {url}"))?
+    {
+        buf.extend_from_slice(&chunk);
+        while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=i).collect();
+            handle(&line)?;
+        }
+    }
+    handle(&buf)?;
+    if !done {
+        bail!("{url}: stream ended before success");
+    }
+    Ok(())
+}
+
+/// Summarize `prompt` with the built-in [`SYSTEM_PROMPT`] and the model's
+/// own generation options, retrying for at most `max_elapsed`
+/// (`ollama.max_elapsed_secs`).
+pub async fn summarize_with_ollama(
+    client: &Client,
+    base: &str,
+    model: &str,
+    prompt: &str,
+    max_elapsed: Duration,
+) -> Result<SummaryResult> {
+    let req = Request {
+        kind: PromptKind::Summary,
+        system: Some(SYSTEM_PROMPT),
+        prompt,
+        format: None,
+        deadline: None,
+    };
+    summarize_with_ollama_options(client, base, model, req, &Options::default(), max_elapsed).await
+}
+
+/// Summarize `req.prompt`, retrying transient failures for at most
+/// `max_elapsed`.
+///
+/// `req.system` is sent as a `system` message; with `None` the model's own
+/// prompt (e.g. the `Modelfile` SYSTEM block) applies. `req.format` is passed
+/// as Ollama's `format` schema. `options` override the model's generation
+/// parameters for this request.
+///
+/// Token counts and timings are the ones Ollama reports.
+pub async fn summarize_with_ollama_options(
+    client: &Client,
+    base: &str,
+    model: &str,
+    req: Request<'_>,
+    options: &Options,
+    max_elapsed: Duration,
+) -> Result<SummaryResult> {
+    let url = format!("{}/api/chat", base.trim_end_matches('/'));
+    let body = chat_body(model, &req, options, false);
+    let in_tok: usize = body.messages.iter().map(|m| count_tokens(m.content)).sum();
 
-            let r: ChatResp = resp
-                .json()
-                .await
-                .map_err(|e| backoff::Error::transient(anyhow!("decode: {e:?}")))?;
+    let r: ChatResp = post_json(client, &url, None, &body, max_elapsed).await?;
+    Ok(r.stats.result(r.message.content, in_tok, false))
+}
 
-            let raw = r.message.content;
-            let out_tok = BPE.encode_with_special_tokens(&raw).len();
+/// Like [`summarize_with_ollama_options`], but streams the reply and logs
+/// progress while it is generated.
+///
+/// Reading stops early at `req.deadline` or once `max_tokens` chunks (one
+/// token each) have arrived; the output so far is then returned with
+/// `truncated` set. Only getting the stream started is retried.
+pub async fn stream_with_ollama(
+    client: &Client,
+    base: &str,
+    model: &str,
+    req: Request<'_>,
+    options: &Options,
+    max_tokens: Option<usize>,
+    max_elapsed: Duration,
+) -> Result<SummaryResult> {
+    let url = format!("{}/api/chat", base.trim_end_matches('/'));
+    let body = chat_body(model, &req, options, true);
+    let prompt_tokens = body.messages.iter().map(|m| count_tokens(m.content)).sum();
+    // No stats arrive for a reply cut off before Ollama finished it.
+    let truncated = |text: String| Stats::default().result(text, prompt_tokens, true);
 
-            Ok::<(String, usize), backoff::Error<anyhow::Error>>((raw, out_tok))
+    let request = || {
+        let r = client.post(&url).json(&body);
+        // The client-wide timeout would cut the stream off before the deadline.
+        match req.deadline {
+            Some(d) => r.timeout(d.saturating_duration_since(Instant::now()) + PROGRESS_EVERY),
+            None => r,
         }
     };
+    let started = Instant::now();
+    let opened = send_streaming(request, max_elapsed);
+    let mut resp = match req.deadline {
+        Some(d) => match timeout_at(d, opened).await {
+            Ok(resp) => resp?,
+            Err(_) => return Ok(truncated(String::new())),
+        },
+        None => opened.await?,
+    };
 
-    let (summary, out_tok) = retry(backoff, op).await?;
-    Ok((summary, in_tok, out_tok))
+    let mut text = String::new();
+    let mut chunks = 0usize;
+    let mut buf = Vec::new();
+    let mut next_log = started + PROGRESS_EVERY;
+    loop {
+        let chunk = match req.deadline {
+            Some(d) => match timeout_at(d, resp.chunk()).await {
+                Ok(chunk) => chunk,
+                Err(_) => {
+                    warn!("Generation cut off at the deadline after {chunks} chunks");
+                    return Ok(truncated(text));
+                }
+            },
+            None => resp.chunk().await,
+        };
+        let chunk = chunk.with_context(|| format!("reading {url}"))?;
+        let ended = chunk.is_none();
+        match chunk {
+            Some(chunk) => buf.extend_from_slice(&chunk),
+            // The last line may lack its newline.
+            None => buf.push(b'\n'),
+        }
+        while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=i).collect();
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let c: ChatChunk = serde_json::from_slice(&line)
+                .with_context(|| format!("{url}: undecodable stream line"))?;
+            if let Some(err) = c.error {
+                bail!("{url}: {err}");
+            }
+            if let Some(m) = c.message {
+                text.push_str(&m.content);
+                chunks += 1;
+            }
+            if c.done {
+                return Ok(c.stats.result(text, prompt_tokens, false));
+            }
+            if max_tokens.is_some_and(|max| chunks >= max) {
+                warn!("Generation stopped at the {chunks}-chunk cap");
+                return Ok(truncated(text));
+            }
+        }
+        if ended {
+            bail!("{url}: stream ended before the reply was done");
+        }
+        if Instant::now() >= next_log {
+            info!(
+                "Generating with {model}: {chunks} chunks after {}s",
+                started.elapsed().as_secs()
+            );
+            next_log += PROGRESS_EVERY;
+        }
+    }
+}
+
+fn chat_body<'a>(
+    model: &'a str,
+    req: &Request<'a>,
+    options: &'a Options,
+    stream: bool,
+) -> ChatReq<'a> {
+    let mut messages = Vec::with_capacity(2);
+    if let Some(system) = req.system {
+        messages.push(Msg {
+            role: "system",
+            content: system,
+        });
+    }
+    messages.push(Msg {
+        role: "user",
+        content: req.prompt,
+    });
+    ChatReq {
+        model,
+        stream,
+        keep_alive: Some("5m"),
+        messages,
+        options,
+        format: req.format,
+    }
+}
+
+/// [`Summarizer`] backed by a local Ollama server's `/api/chat`.
+pub struct OllamaSummarizer {
+    pub client: Client,
+    pub base_url: String,
+    pub model: String,
+    /// Options sent with every request.
+    pub options: Options,
+    /// Per prompt kind overrides, applied on top of `options`.
+    pub prompt_options: HashMap<PromptKind, Options>,
+    /// Stream replies, logging progress and honoring request deadlines.
+    pub stream: bool,
+    /// Client-side cap on streamed reply tokens.
+    pub max_output_tokens: Option<usize>,
+    /// Max retry duration for a single call.
+    pub max_elapsed: Duration,
+}
+
+impl Summarizer for OllamaSummarizer {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(async move {
+            let options = match self.prompt_options.get(&req.kind) {
+                Some(o) => self.options.merged(o),
+                None => self.options.clone(),
+            };
+            if self.stream {
+                return stream_with_ollama(
+                    &self.client,
+                    &self.base_url,
+                    &self.model,
+                    req,
+                    &options,
+                    self.max_output_tokens,
+                    self.max_elapsed,
+                )
+                .await;
+            }
+            summarize_with_ollama_options(
+                &self.client,
+                &self.base_url,
+                &self.model,
+                req,
+                &options,
+                self.max_elapsed,
+            )
+            .await
+        })
+    }
 }
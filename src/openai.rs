@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::summarizer::{Request, Summarizer, SummaryResult, count_tokens, post_json};
+
+#[derive(Serialize)]
+struct Msg<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChatReq<'a> {
+    model: &'a str,
+    stream: bool,
+    messages: Vec<Msg<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat<'a>>,
+}
+
+#[derive(Serialize)]
+struct ResponseFormat<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    json_schema: JsonSchema<'a>,
+}
+
+#[derive(Serialize)]
+struct JsonSchema<'a> {
+    name: &'a str,
+    schema: Value,
+    strict: bool,
+}
+
+/// Keywords OpenAI's strict structured outputs reject.
+const UNSUPPORTED_IN_STRICT: [&str; 15] = [
+    "contains",
+    "maxContains",
+    "maxItems",
+    "maxLength",
+    "maxProperties",
+    "minContains",
+    "minItems",
+    "minLength",
+    "minProperties",
+    "multipleOf",
+    "patternProperties",
+    "propertyNames",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+    "uniqueItems",
+];
+
+/// `schema` without the keywords strict mode rejects, at every level. The
+/// constraints dropped stay the caller's to check, as
+/// [`StructuredSummary::parse`](crate::structured::StructuredSummary::parse)
+/// does for [`SCHEMA`](crate::structured::SCHEMA).
+fn strict_schema(schema: &Value) -> Value {
+    let Value::Object(map) = schema else {
+        return schema.clone();
+    };
+    let subschemas = |v: &Value| match v {
+        Value::Array(items) => items.iter().map(strict_schema).collect(),
+        other => strict_schema(other),
+    };
+    map.iter()
+        .filter(|(key, _)| !UNSUPPORTED_IN_STRICT.contains(&key.as_str()))
+        .map(|(key, value)| {
+            let value = match (key.as_str(), value) {
+                // Property names are not keywords.
+                ("properties" | "$defs", Value::Object(props)) => props
+                    .iter()
+                    .map(|(name, s)| (name.clone(), strict_schema(s)))
+                    .collect(),
+                ("items" | "anyOf" | "additionalProperties", v) => subschemas(v),
+                (_, v) => v.clone(),
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct ChatResp {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMsg,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMsg {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
+}
+
+/// Backend for OpenAI-compatible `/v1/chat/completions` servers such as
+/// llama.cpp's `llama-server`, vLLM or LM Studio.
+///
+/// Token counts come from the response's `usage` block when the server sends
+/// one, and are estimated with the cl100k tokenizer otherwise.
+pub struct OpenAiSummarizer {
+    pub client: Client,
+    pub base_url: String,
+    pub model: String,
+    /// Sent as a bearer token when set.
+    pub api_key: Option<String>,
+    /// Max retry duration for a single call.
+    pub max_elapsed: Duration,
+}
+
+impl Summarizer for OpenAiSummarizer {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/v1/chat/completions",
+                self.base_url.trim_end_matches('/')
+            );
+            let mut messages = Vec::with_capacity(2);
+            if let Some(system) = req.system {
+                messages.push(Msg {
+                    role: "system",
+                    content: system,
+                });
+            }
+            messages.push(Msg {
+                role: "user",
+                content: req.prompt,
+            });
+            let body = ChatReq {
+                model: &self.model,
+                stream: false,
+                messages,
+                response_format: req.format.map(|schema| ResponseFormat {
+                    kind: "json_schema",
+                    json_schema: JsonSchema {
+                        name: "summary",
+                        schema: strict_schema(schema),
+                        strict: true,
+                    },
+                }),
+            };
+            let resp: ChatResp = post_json(
+                &self.client,
+                &url,
+                self.api_key.as_deref(),
+                &body,
+                self.max_elapsed,
+            )
+            .await?;
+
+            let text = resp
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|c| c.message.content)
+                .ok_or_else(|| anyhow!("{url} returned no choices"))?;
+            let (prompt_tokens, completion_tokens) = match resp.usage {
+                Some(u) => (u.prompt_tokens, u.completion_tokens),
+                None => (
+                    count_tokens(req.system.unwrap_or_default()) + count_tokens(req.prompt),
+                    count_tokens(&text),
+                ),
+            };
+            Ok(SummaryResult {
+                text,
+                prompt_tokens,
+                completion_tokens,
+                ..Default::default()
+            })
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use serde::Deserialize;
+
+/// Kinds of prompt sent to the summarizer; backends may tune generation per
+/// kind.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptKind {
+    /// Throwaway request that loads the model before the first topic.
+    Warmup,
+    /// Summary of one topic excerpt.
+    Summary,
+    /// Summary combining the summaries of several excerpts of one topic.
+    Reduce,
+}
+
+impl PromptKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PromptKind::Warmup => "warmup",
+            PromptKind::Summary => "summary",
+            PromptKind::Reduce => "reduce",
+        }
+    }
+}
+
+/// Version of the built-in system prompts. Bump it with every wording change
+/// so logs show which prompt produced a digest; cached summaries are keyed on
+/// the prompt text itself.
+pub const SYSTEM_PROMPT_VERSION: u32 = 2;
+
+/// System prompt for free-text summaries (`summarize.structured = false`).
+pub const SYSTEM_PROMPT: &str = "You are summarizing ONE forum thread excerpt.
+Return a concise summary in plain text, consisting of a list of '- ' bullet points with key facts.
+Do NOT include post IDs, timestamps, author names, or URLs.";
+
+/// System prompt for structured summaries, sent with
+/// [`crate::structured::SCHEMA`] as the output format.
+pub const STRUCTURED_SYSTEM_PROMPT: &str = "You are summarizing ONE forum thread excerpt.
+Reply with a single JSON object:
+- headline: one sentence stating what the thread is about.
+- bullets: the key facts, one short sentence each.
+- decisions: decisions the participants reached.
+- open_questions: questions left unanswered.
+- action_items: concrete next steps someone committed to.
+Use empty lists when nothing applies.
+Do NOT include post IDs, timestamps, author names, or URLs.";
+
+/// Template every summary prompt is rendered from. It is part of the summary
+/// cache key, so editing it invalidates previously cached summaries.
+pub const PROMPT_TEMPLATE: &str = "Thread: {title}\n\nContent excerpt:\n---\n{body}\n---";
+
+/// Template for combining partial summaries of a thread too long for one
+/// prompt. Like [`PROMPT_TEMPLATE`], it is part of the summary cache key.
+pub const REDUCE_TEMPLATE: &str = "Thread: {title}\n\nSummaries of consecutive parts of the thread, oldest first:\n---\n{body}\n---\nCombine them into one summary of the whole thread.";
+
+pub fn build_prompt(topic_title: &str, chunk: &str) -> String {
+    render(PROMPT_TEMPLATE, topic_title, chunk)
+}
+
+pub fn build_reduce_prompt(topic_title: &str, summaries: &str) -> String {
+    render(REDUCE_TEMPLATE, topic_title, summaries)
+}
+
+fn render(template: &str, title: &str, body: &str) -> String {
+    let (head, tail) = template
+        .split_once("{body}")
+        .expect("template has a {body} placeholder");
+    format!("{}{}{}", head.replace("{title}", title), body, tail)
+}
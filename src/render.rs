@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::Result;
+use rss::{CategoryBuilder, ChannelBuilder, ItemBuilder};
+use time::format_description::well_known::Rfc2822;
+use tracing::warn;
+
+use crate::structured::StructuredSummary;
+use crate::{DigestItem, Forum};
+
+/// Stylesheet shipped with the repository, written next to digests that are
+/// rendered outside of `public/`.
+pub const STYLE_CSS: &str = include_str!("../public/style.css");
+
+/// Shown in place of the summary of a topic that could not be summarized.
+pub const FAILED_NOTICE: &str = "Could not summarize this topic.";
+
+/// Digest items collected from a single forum.
+pub struct ForumDigest<'a> {
+    pub forum: &'a Forum,
+    pub items: Vec<DigestItem>,
+    /// Topics among `items` whose summary failed; rendered with
+    /// [`FAILED_NOTICE`].
+    pub failed: HashSet<u64>,
+}
+
+/// Render the HTML digest page.
+///
+/// A single forum renders as a flat list of topics; several forums render one
+/// section per forum, in the order given. All interpolated text is
+/// HTML-escaped.
+pub fn render_html(title: &str, sections: &[ForumDigest]) -> String {
+    let topics: usize = sections.iter().map(|s| s.items.len()).sum();
+    let title = escape_html(title);
+    let mut html = String::with_capacity(2048 + topics * 2048);
+    html.push_str(&format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title><link rel=\"stylesheet\" href=\"./style.css\"></head><body>"
+    ));
+    html.push_str(&format!(
+        "<h1>{title}</h1><p><a href=\"rss.xml\">RSS Feed</a></p>"
+    ));
+
+    let sectioned = sections.len() > 1;
+    let heading = if sectioned { "h3" } else { "h2" };
+    for section in sections {
+        if sectioned {
+            html.push_str(&format!(
+                "<section><h2><a href=\"{url}\">{name}</a></h2>",
+                url = escape_html(&section.forum.base_url),
+                name = escape_html(&section.forum.name)
+            ));
+        }
+        for item in &section.items {
+            html.push_str(&format!(
+                "<{heading}><a href=\"{url}\">{title}</a></{heading}>",
+                url = escape_html(&item.url),
+                title = escape_html(&item.title)
+            ));
+            if section.failed.contains(&item.topic_id) {
+                html.push_str(&format!("<p class=\"failed\">{FAILED_NOTICE}</p>"));
+            } else if let Some(s) = &item.structured {
+                html.push_str(&render_structured(s));
+            } else if !item.summary.is_empty() {
+                let summary = escape_html(&item.summary).replace('\n', "<br>");
+                html.push_str(&format!("<p>{summary}</p>"));
+            }
+        }
+        if sectioned {
+            html.push_str("</section>");
+        }
+    }
+
+    html.push_str("</body></html>");
+    html
+}
+
+/// Lay out a structured summary: the headline, its bullets, then one labelled
+/// list per non-empty section.
+fn render_structured(s: &StructuredSummary) -> String {
+    let list = |items: &[String]| {
+        let lis: String = items
+            .iter()
+            .map(|i| format!("<li>{}</li>", escape_html(i)))
+            .collect();
+        format!("<ul>{lis}</ul>")
+    };
+    let mut html = format!(
+        "<div class=\"summary\"><p class=\"headline\">{}</p>{}",
+        escape_html(&s.headline),
+        list(&s.bullets)
+    );
+    for (label, items) in s.sections() {
+        html.push_str(&format!("<p class=\"label\">{label}</p>{}", list(items)));
+    }
+    html.push_str("</div>");
+    html
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Render the RSS channel for the digest.
+///
+/// When several forums are rendered together, every item is tagged with its
+/// forum name as an RSS category. Items whose date cannot be formatted are
+/// published without `pubDate`.
+pub fn render_rss(title: &str, link: &str, description: &str, sections: &[ForumDigest]) -> String {
+    let sectioned = sections.len() > 1;
+    let mut items = Vec::new();
+    for section in sections {
+        for item_data in &section.items {
+            let pub_date = match item_data.created_at.format(&Rfc2822) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    warn!("No pubDate for topic {}: {e}", item_data.topic_id);
+                    None
+                }
+            };
+            let summary = if section.failed.contains(&item_data.topic_id) {
+                Some(FAILED_NOTICE.to_string())
+            } else {
+                (!item_data.summary.is_empty()).then(|| item_data.summary.clone())
+            };
+            let categories = if sectioned {
+                vec![
+                    CategoryBuilder::default()
+                        .name(section.forum.name.clone())
+                        .build(),
+                ]
+            } else {
+                Vec::new()
+            };
+            let item = ItemBuilder::default()
+                .title(item_data.title.clone())
+                .link(item_data.url.clone())
+                .author(Some(item_data.author.clone()))
+                .description(summary)
+                .pub_date(pub_date)
+                .categories(categories)
+                .build();
+            items.push(item);
+        }
+    }
+
+    let channel = ChannelBuilder::default()
+        .title(title.to_string())
+        .link(link.to_string())
+        .description(description.to_string())
+        .items(items)
+        .build();
+    channel.to_string()
+}
+
+/// Write `index.html` and `rss.xml` into `dir`, along with the stylesheet if
+/// the directory does not already have one.
+pub fn write_digest(
+    dir: &Path,
+    title: &str,
+    link: &str,
+    description: &str,
+    sections: &[ForumDigest],
+) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("index.html"), render_html(title, sections))?;
+    std::fs::write(
+        dir.join("rss.xml"),
+        render_rss(title, link, description, sections),
+    )?;
+    let css = dir.join("style.css");
+    if !css.exists() {
+        std::fs::write(css, STYLE_CSS)?;
+    }
+    Ok(())
+}
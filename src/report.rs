@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::summarizer::Usage;
+
+/// Pipeline stage a failure happened in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Fetch,
+    Summarize,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Fetch => "fetch",
+            Stage::Summarize => "summarize",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Stage> {
+        match name {
+            "fetch" => Some(Stage::Fetch),
+            "summarize" => Some(Stage::Summarize),
+            _ => None,
+        }
+    }
+}
+
+/// A topic (or, without `topic_id`, a whole forum listing) that was skipped
+/// because of an error.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    pub forum: String,
+    pub topic_id: Option<u64>,
+    pub title: Option<String>,
+    pub stage: Stage,
+    pub reason: String,
+}
+
+/// Outcome of one invocation of the binary, written as `run-report.json`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunReport {
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+    pub topics_fetched: usize,
+    pub topics_summarized: usize,
+    /// Summaries kept although generation was cut off; not cached.
+    pub topics_truncated: usize,
+    /// LLM calls of the summarize stage; cache hits make none.
+    #[serde(default)]
+    pub usage: Usage,
+    pub failures: Vec<Failure>,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        RunReport {
+            started_at: OffsetDateTime::now_utc(),
+            finished_at: None,
+            topics_fetched: 0,
+            topics_summarized: 0,
+            topics_truncated: 0,
+            usage: Usage::default(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+impl RunReport {
+    /// Stamp the finish time and write the report as pretty JSON.
+    pub fn write(&mut self, path: &Path) -> Result<()> {
+        self.finished_at = Some(OffsetDateTime::now_utc());
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
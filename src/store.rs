@@ -0,0 +1,558 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use time::OffsetDateTime;
+
+use crate::report::{Failure, Stage};
+use crate::{DigestItem, Post};
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how
+/// many have run; append new entries, never edit old ones.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE topics (
+    forum      TEXT    NOT NULL,
+    id         INTEGER NOT NULL,
+    title      TEXT    NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (forum, id)
+);
+CREATE TABLE posts (
+    forum      TEXT    NOT NULL,
+    id         INTEGER NOT NULL,
+    topic_id   INTEGER NOT NULL,
+    username   TEXT    NOT NULL,
+    created_at INTEGER NOT NULL,
+    cooked     TEXT    NOT NULL,
+    PRIMARY KEY (forum, id)
+);
+CREATE INDEX posts_by_topic ON posts (forum, topic_id, created_at);
+CREATE TABLE runs (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    command      TEXT    NOT NULL,
+    started_at   INTEGER NOT NULL,
+    finished_at  INTEGER,
+    status       TEXT    NOT NULL,
+    cutoff_hours INTEGER NOT NULL,
+    model        TEXT    NOT NULL
+);
+CREATE TABLE digest_items (
+    run_id     INTEGER NOT NULL REFERENCES runs (id),
+    forum      TEXT    NOT NULL,
+    position   INTEGER NOT NULL,
+    topic_id   INTEGER NOT NULL,
+    post_id    INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    author     TEXT    NOT NULL,
+    title      TEXT    NOT NULL,
+    url        TEXT    NOT NULL,
+    summary    TEXT    NOT NULL,
+    PRIMARY KEY (run_id, forum, position)
+);
+"#,
+    r#"
+CREATE TABLE summary_cache (
+    key        TEXT    PRIMARY KEY,
+    model      TEXT    NOT NULL,
+    summary    TEXT    NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX summary_cache_by_model ON summary_cache (model);
+"#,
+    r#"
+ALTER TABLE topics ADD COLUMN high_water INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE posts ADD COLUMN post_number INTEGER NOT NULL DEFAULT 0;
+"#,
+    r#"
+CREATE TABLE failures (
+    run_id   INTEGER NOT NULL REFERENCES runs (id),
+    forum    TEXT    NOT NULL,
+    topic_id INTEGER,
+    title    TEXT,
+    stage    TEXT    NOT NULL,
+    reason   TEXT    NOT NULL
+);
+CREATE INDEX failures_by_run ON failures (run_id);
+"#,
+    r#"
+ALTER TABLE digest_items ADD COLUMN structured TEXT;
+"#,
+    r#"
+ALTER TABLE topics ADD COLUMN private INTEGER NOT NULL DEFAULT 0;
+"#,
+    r#"
+ALTER TABLE topics ADD COLUMN category TEXT;
+ALTER TABLE topics ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+"#,
+    r#"
+ALTER TABLE topics ADD COLUMN synced_since INTEGER;
+"#,
+];
+
+/// A topic known to the store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredTopic {
+    pub id: u64,
+    pub title: String,
+    /// Category slug path as of the last fetch, such as `parent/child`.
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Metadata of one pipeline run.
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub id: i64,
+    pub command: String,
+    pub started_at: OffsetDateTime,
+    pub finished_at: Option<OffsetDateTime>,
+    pub status: String,
+    pub cutoff_hours: i64,
+    pub model: String,
+}
+
+/// SQLite archive of fetched topics and posts, digest items and run metadata.
+///
+/// Forums are keyed by base URL, except in digest items: several
+/// `[[forums]]` entries may digest one forum with different filters, so those
+/// are keyed by [`Forum::slug`](crate::Forum::slug). Timestamps are stored as
+/// UTC unix nanoseconds so they sort and compare numerically.
+pub struct Store {
+    conn: Connection,
+}
+
+fn ts(t: OffsetDateTime) -> i64 {
+    t.unix_timestamp_nanos() as i64
+}
+
+fn from_ts(n: i64) -> rusqlite::Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(n as i128).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Integer, e.into())
+    })
+}
+
+impl Store {
+    /// Open (or create) the database at `path` and bring its schema up to date.
+    pub fn open(path: &Path) -> Result<Store> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn =
+            Connection::open(path).with_context(|| format!("opening store {}", path.display()))?;
+        Store::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Store> {
+        Store::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Store> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let version: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)
+                .with_context(|| format!("applying store migration {}", i + 1))?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        Ok(Store { conn })
+    }
+
+    /// Number of migrations applied to the open database.
+    pub fn schema_version(&self) -> Result<usize> {
+        Ok(self
+            .conn
+            .pragma_query_value(None, "user_version", |r| r.get(0))?)
+    }
+
+    /// Insert or update a topic and its posts.
+    ///
+    /// `high_water` is the highest post number seen for the topic; the stored
+    /// mark never moves backwards.
+    pub fn upsert_topic(
+        &mut self,
+        forum: &str,
+        id: u64,
+        title: &str,
+        posts: &[Post],
+        high_water: u64,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO topics (forum, id, title, fetched_at, high_water) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (forum, id) DO UPDATE SET title = excluded.title, fetched_at = excluded.fetched_at,
+             high_water = MAX(high_water, excluded.high_water)",
+            params![forum, id, title, ts(OffsetDateTime::now_utc()), high_water],
+        )?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO posts (forum, id, topic_id, username, created_at, cooked, post_number)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (forum, id) DO UPDATE SET username = excluded.username, cooked = excluded.cooked",
+            )?;
+            for p in posts {
+                stmt.execute(params![
+                    forum,
+                    p.id,
+                    id,
+                    p.username,
+                    ts(p.created_at),
+                    p.cooked,
+                    p.post_number,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Highest post number seen for a topic, or 0 if it was never fetched.
+    pub fn high_water(&self, forum: &str, topic_id: u64) -> Result<u64> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT high_water FROM topics WHERE forum = ?1 AND id = ?2",
+                params![forum, topic_id],
+                |r| r.get(0),
+            )
+            .optional()?
+            .unwrap_or(0))
+    }
+
+    /// Cutoff of the last sync of a topic: every post created since is
+    /// stored. `None` if the topic was never synced with one.
+    pub fn synced_since(&self, forum: &str, topic_id: u64) -> Result<Option<OffsetDateTime>> {
+        let since: Option<Option<i64>> = self
+            .conn
+            .query_row(
+                "SELECT synced_since FROM topics WHERE forum = ?1 AND id = ?2",
+                params![forum, topic_id],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(since.flatten().map(from_ts).transpose()?)
+    }
+
+    /// Record the cutoff a stored topic was synced for. Topics not in the
+    /// store are ignored.
+    pub fn set_synced_since(
+        &self,
+        forum: &str,
+        topic_id: u64,
+        since: OffsetDateTime,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE topics SET synced_since = ?3 WHERE forum = ?1 AND id = ?2",
+            params![forum, topic_id, ts(since)],
+        )?;
+        Ok(())
+    }
+
+    /// Record whether a stored topic sits in a read-restricted category.
+    /// Topics not in the store are ignored.
+    pub fn set_private(&self, forum: &str, topic_id: u64, private: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE topics SET private = ?3 WHERE forum = ?1 AND id = ?2",
+            params![forum, topic_id, private],
+        )?;
+        Ok(())
+    }
+
+    /// Record the category slug path and tags of a stored topic, for
+    /// filtering. Topics not in the store are ignored.
+    pub fn set_labels(
+        &self,
+        forum: &str,
+        topic_id: u64,
+        category: Option<&str>,
+        tags: &[String],
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE topics SET category = ?3, tags = ?4 WHERE forum = ?1 AND id = ?2",
+            params![forum, topic_id, category, serde_json::to_string(tags)?],
+        )?;
+        Ok(())
+    }
+
+    /// Ids of the stored topics marked private with [`Store::set_private`].
+    pub fn private_topics(&self, forum: &str) -> Result<HashSet<u64>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id FROM topics WHERE forum = ?1 AND private")?;
+        let rows = stmt.query_map(params![forum], |r| r.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Ids of the stored posts of a topic.
+    pub fn known_post_ids(&self, forum: &str, topic_id: u64) -> Result<HashSet<u64>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id FROM posts WHERE forum = ?1 AND topic_id = ?2")?;
+        let rows = stmt.query_map(params![forum, topic_id], |r| r.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Topics with at least one post at or after `cutoff`, most recently
+    /// active first.
+    pub fn topics_active_since(
+        &self,
+        forum: &str,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<StoredTopic>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT t.id, t.title, t.category, t.tags FROM topics t
+             JOIN posts p ON p.forum = t.forum AND p.topic_id = t.id
+             WHERE t.forum = ?1 AND p.created_at >= ?2
+             GROUP BY t.id
+             ORDER BY MAX(p.created_at) DESC",
+        )?;
+        let rows = stmt.query_map(params![forum, ts(cutoff)], |r| {
+            Ok(StoredTopic {
+                id: r.get(0)?,
+                title: r.get(1)?,
+                category: r.get(2)?,
+                tags: serde_json::from_str(&r.get::<_, String>(3)?).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        3,
+                        rusqlite::types::Type::Text,
+                        e.into(),
+                    )
+                })?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Posts of a topic created at or after `cutoff`, oldest first.
+    pub fn posts_since(
+        &self,
+        forum: &str,
+        topic_id: u64,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<Post>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, cooked, created_at, username, post_number FROM posts
+             WHERE forum = ?1 AND topic_id = ?2 AND created_at >= ?3
+             ORDER BY created_at, id",
+        )?;
+        let rows = stmt.query_map(params![forum, topic_id, ts(cutoff)], |r| {
+            Ok(Post {
+                id: r.get(0)?,
+                cooked: r.get(1)?,
+                created_at: from_ts(r.get(2)?)?,
+                username: r.get(3)?,
+                post_number: r.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Record the start of a run and return its id.
+    pub fn begin_run(&self, command: &str, cutoff_hours: i64, model: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO runs (command, started_at, status, cutoff_hours, model)
+             VALUES (?1, ?2, 'running', ?3, ?4)",
+            params![command, ts(OffsetDateTime::now_utc()), cutoff_hours, model],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Record how a run ended: `ok`, `partial` or `failed`.
+    pub fn finish_run(&self, run_id: i64, status: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE runs SET finished_at = ?2, status = ?3 WHERE id = ?1",
+            params![run_id, ts(OffsetDateTime::now_utc()), status],
+        )?;
+        Ok(())
+    }
+
+    pub fn run(&self, run_id: i64) -> Result<Option<Run>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id, command, started_at, finished_at, status, cutoff_hours, model
+                 FROM runs WHERE id = ?1",
+                params![run_id],
+                run_from_row,
+            )
+            .optional()?)
+    }
+
+    /// The most recent run of `command` that finished, possibly with some
+    /// topics failed.
+    pub fn latest_run(&self, command: &str) -> Result<Option<Run>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id, command, started_at, finished_at, status, cutoff_hours, model
+                 FROM runs WHERE command = ?1 AND status IN ('ok', 'partial')
+                 ORDER BY id DESC LIMIT 1",
+                params![command],
+                run_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Store the digest items produced by a run for the forum entry with slug
+    /// `forum`, in order.
+    pub fn save_digest_items(
+        &mut self,
+        run_id: i64,
+        forum: &str,
+        items: &[DigestItem],
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM digest_items WHERE run_id = ?1 AND forum = ?2",
+            params![run_id, forum],
+        )?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO digest_items
+                 (run_id, forum, position, topic_id, post_id, created_at, author, title, url, summary, structured)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )?;
+            for (pos, item) in items.iter().enumerate() {
+                let structured = item
+                    .structured
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?;
+                stmt.execute(params![
+                    run_id,
+                    forum,
+                    pos,
+                    item.topic_id,
+                    item.post_id,
+                    ts(item.created_at),
+                    item.author,
+                    item.title,
+                    item.url,
+                    item.summary,
+                    structured,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn digest_items(&self, run_id: i64, forum: &str) -> Result<Vec<DigestItem>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT post_id, topic_id, created_at, author, title, url, summary, structured
+             FROM digest_items WHERE run_id = ?1 AND forum = ?2 ORDER BY position",
+        )?;
+        let rows = stmt.query_map(params![run_id, forum], |r| {
+            Ok(DigestItem {
+                post_id: r.get(0)?,
+                topic_id: r.get(1)?,
+                created_at: from_ts(r.get(2)?)?,
+                author: r.get(3)?,
+                title: r.get(4)?,
+                url: r.get(5)?,
+                summary: r.get(6)?,
+                structured: r
+                    .get::<_, Option<String>>(7)?
+                    .map(|s| serde_json::from_str(&s))
+                    .transpose()
+                    .map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            7,
+                            rusqlite::types::Type::Text,
+                            e.into(),
+                        )
+                    })?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn record_failure(&self, run_id: i64, failure: &Failure) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO failures (run_id, forum, topic_id, title, stage, reason)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                run_id,
+                failure.forum,
+                failure.topic_id,
+                failure.title,
+                failure.stage.as_str(),
+                failure.reason,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Failures recorded by a run, in the order they happened.
+    pub fn failures(&self, run_id: i64) -> Result<Vec<Failure>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT forum, topic_id, title, stage, reason FROM failures
+             WHERE run_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![run_id], |r| {
+            let name: String = r.get(3)?;
+            let stage = Stage::from_name(&name).ok_or_else(|| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    3,
+                    rusqlite::types::Type::Text,
+                    format!("unknown stage {name:?}").into(),
+                )
+            })?;
+            Ok(Failure {
+                forum: r.get(0)?,
+                topic_id: r.get(1)?,
+                title: r.get(2)?,
+                stage,
+                reason: r.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Look up a cached summary by [`crate::summary_cache_key`].
+    pub fn cached_summary(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT summary FROM summary_cache WHERE key = ?1",
+                params![key],
+                |r| r.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn cache_summary(&self, key: &str, model: &str, summary: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO summary_cache (key, model, summary, created_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (key) DO UPDATE SET summary = excluded.summary, created_at = excluded.created_at",
+            params![key, model, summary, ts(OffsetDateTime::now_utc())],
+        )?;
+        Ok(())
+    }
+
+    /// Drop cached summaries produced by `model`, or all of them when `None`.
+    /// Returns the number of entries removed.
+    pub fn invalidate_summaries(&self, model: Option<&str>) -> Result<usize> {
+        Ok(match model {
+            Some(m) => self
+                .conn
+                .execute("DELETE FROM summary_cache WHERE model = ?1", params![m])?,
+            None => self.conn.execute("DELETE FROM summary_cache", [])?,
+        })
+    }
+}
+
+fn run_from_row(r: &Row) -> rusqlite::Result<Run> {
+    Ok(Run {
+        id: r.get(0)?,
+        command: r.get(1)?,
+        started_at: from_ts(r.get(2)?)?,
+        finished_at: r.get::<_, Option<i64>>(3)?.map(from_ts).transpose()?,
+        status: r.get(4)?,
+        cutoff_hours: r.get(5)?,
+        model: r.get(6)?,
+    })
+}
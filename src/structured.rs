@@ -0,0 +1,233 @@
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::warn;
+
+use crate::strip_post_tags;
+use crate::summarizer::{Request, Summarizer, SummaryResult};
+
+/// Summary of one topic as structured fields, requested with [`SCHEMA`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredSummary {
+    /// One sentence on what the topic is about.
+    pub headline: String,
+    /// Key facts.
+    pub bullets: Vec<String>,
+    pub decisions: Vec<String>,
+    pub open_questions: Vec<String>,
+    pub action_items: Vec<String>,
+}
+
+/// JSON schema of [`StructuredSummary`], sent as the backend's output format.
+/// Not every backend enforces its length limits, so
+/// [`StructuredSummary::parse`] checks them again.
+pub static SCHEMA: LazyLock<Value> = LazyLock::new(|| {
+    let list = json!({"type": "array", "items": {"type": "string", "minLength": 1}});
+    json!({
+        "type": "object",
+        "properties": {
+            "headline": {"type": "string", "minLength": 1},
+            "bullets": {"type": "array", "items": {"type": "string", "minLength": 1}, "minItems": 1},
+            "decisions": list,
+            "open_questions": list,
+            "action_items": list,
+        },
+        "required": ["headline", "bullets", "decisions", "open_questions", "action_items"],
+        "additionalProperties": false,
+    })
+});
+
+impl StructuredSummary {
+    /// Parse model output and check it against [`SCHEMA`]. `[post:ID]` tags
+    /// are stripped from every field.
+    pub fn parse(text: &str) -> Result<StructuredSummary> {
+        let s: StructuredSummary =
+            serde_json::from_str(text.trim()).context("reply does not match the schema")?;
+        let s = s.scrubbed();
+        if s.headline.is_empty() {
+            bail!("`headline` must not be empty");
+        }
+        if s.bullets.is_empty() {
+            bail!("`bullets` must list at least one item");
+        }
+        let lists = [&s.bullets, &s.decisions, &s.open_questions, &s.action_items];
+        if lists.iter().any(|l| l.iter().any(String::is_empty)) {
+            bail!("list items must not be empty");
+        }
+        Ok(s)
+    }
+
+    /// Recover the fields of a reply cut off mid-JSON: the longest prefix
+    /// that parses once its open strings, lists and objects are closed.
+    /// Missing fields are left empty; `None` without a headline.
+    pub fn salvage(text: &str) -> Option<StructuredSummary> {
+        let text = text.trim();
+        let ends = text
+            .char_indices()
+            .filter(|(_, c)| matches!(c, '"' | ']' | '}'))
+            .map(|(i, _)| i + 1)
+            .rev();
+        let value: Value = std::iter::once(text.len())
+            .chain(ends)
+            .find_map(|end| serde_json::from_str(&close_json(&text[..end])).ok())?;
+        let list = |key: &str| -> Vec<String> {
+            value[key]
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let s = StructuredSummary {
+            headline: value["headline"].as_str().unwrap_or_default().to_string(),
+            bullets: list("bullets"),
+            decisions: list("decisions"),
+            open_questions: list("open_questions"),
+            action_items: list("action_items"),
+        }
+        .scrubbed();
+        let keep = |v: Vec<String>| v.into_iter().filter(|i| !i.is_empty()).collect();
+        let s = StructuredSummary {
+            bullets: keep(s.bullets),
+            decisions: keep(s.decisions),
+            open_questions: keep(s.open_questions),
+            action_items: keep(s.action_items),
+            ..s
+        };
+        (!s.headline.is_empty()).then_some(s)
+    }
+
+    fn scrubbed(self) -> StructuredSummary {
+        let scrub = |v: Vec<String>| -> Vec<String> {
+            v.iter()
+                .map(|s| strip_post_tags(s).trim().to_string())
+                .collect()
+        };
+        StructuredSummary {
+            headline: strip_post_tags(&self.headline).trim().to_string(),
+            bullets: scrub(self.bullets),
+            decisions: scrub(self.decisions),
+            open_questions: scrub(self.open_questions),
+            action_items: scrub(self.action_items),
+        }
+    }
+
+    /// Plain-text rendering: the headline followed by `- ` bullets, with a
+    /// labelled block for each non-empty list.
+    pub fn to_plain_text(&self) -> String {
+        let mut out = self.headline.clone();
+        for b in &self.bullets {
+            out.push_str("\n- ");
+            out.push_str(b);
+        }
+        for (label, items) in self.sections() {
+            out.push_str(&format!("\n{label}:"));
+            for i in items {
+                out.push_str("\n- ");
+                out.push_str(i);
+            }
+        }
+        out
+    }
+
+    /// Labelled lists after the bullets, skipping empty ones.
+    pub fn sections(&self) -> impl Iterator<Item = (&'static str, &[String])> {
+        [
+            ("Decisions", self.decisions.as_slice()),
+            ("Open questions", self.open_questions.as_slice()),
+            ("Action items", self.action_items.as_slice()),
+        ]
+        .into_iter()
+        .filter(|(_, items)| !items.is_empty())
+    }
+}
+
+/// Close the strings, lists and objects left open in a JSON prefix, dropping
+/// a trailing comma.
+fn close_json(prefix: &str) -> String {
+    let mut closers = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    for c in prefix.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+    let mut out = prefix.to_string();
+    if in_string {
+        if escaped {
+            out.pop();
+        }
+        out.push('"');
+    }
+    let kept = out.trim_end().trim_end_matches(',').len();
+    out.truncate(kept);
+    out.extend(closers.iter().rev());
+    out
+}
+
+/// Request a structured summary, asking once more with the validation error
+/// if the first reply does not match [`SCHEMA`].
+///
+/// Returns the parsed summary and the result of the accepted call, with the
+/// usage of a rejected first reply added. A reply cut off by the deadline is
+/// not asked for again: the fields that arrived are kept and the result stays
+/// marked as truncated.
+pub async fn summarize_structured(
+    summarizer: &dyn Summarizer,
+    req: Request<'_>,
+) -> Result<(StructuredSummary, SummaryResult)> {
+    let req = Request {
+        format: Some(&SCHEMA),
+        ..req
+    };
+    let first = summarizer.summarize(req).await?;
+    let err = match StructuredSummary::parse(&first.text) {
+        Ok(s) => return Ok((s, first)),
+        // The deadline has passed, so asking again would be cut off too;
+        // keep what arrived instead.
+        Err(e) if first.truncated => {
+            let s = StructuredSummary::salvage(&first.text)
+                .ok_or_else(|| e.context("reply was cut off"))?;
+            warn!("Structured summary cut off, keeping the fields that arrived");
+            return Ok((s, first));
+        }
+        Err(e) => e,
+    };
+    warn!("Structured summary rejected, asking again: {err:#}");
+    let retry_prompt = format!(
+        "{}\n\nYour previous reply was rejected ({err:#}). Reply again with only a JSON object matching the schema.",
+        req.prompt
+    );
+    let mut second = summarizer
+        .summarize(Request {
+            prompt: &retry_prompt,
+            ..req
+        })
+        .await?;
+    second.add_usage(&first);
+    let s = StructuredSummary::parse(&second.text)
+        .context("structured summary still invalid after re-asking")?;
+    Ok((s, second))
+}
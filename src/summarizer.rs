@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use backoff::{ExponentialBackoff, future::retry};
+use futures::future::BoxFuture;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::time::Instant;
+
+use crate::BPE;
+use crate::config::{Backend, Config};
+use crate::llamacpp::{LlamaCppSummarizer, Params};
+use crate::ollama::{OllamaSummarizer, Options};
+use crate::openai::OpenAiSummarizer;
+use crate::prompt::PromptKind;
+
+/// Output of one summarization call.
+///
+/// Token counts are the backend's own when it reports them, and cl100k
+/// estimates otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SummaryResult {
+    pub text: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Generation was cut short by the deadline or an output cap; `text` is
+    /// what had been generated by then.
+    pub truncated: bool,
+    /// Server-side timings, for backends that report them.
+    pub timings: Option<Timings>,
+}
+
+impl SummaryResult {
+    /// Add the token counts and timings of an earlier call made for the same
+    /// summary, e.g. a rejected reply.
+    pub fn add_usage(&mut self, earlier: &SummaryResult) {
+        self.prompt_tokens += earlier.prompt_tokens;
+        self.completion_tokens += earlier.completion_tokens;
+        if let Some(t) = earlier.timings {
+            let mine = self.timings.get_or_insert_default();
+            mine.total += t.total;
+            mine.load += t.load;
+            mine.eval += t.eval;
+        }
+    }
+}
+
+/// Time a backend spent on one call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Whole request, including loading and prompt evaluation.
+    pub total: Duration,
+    /// Loading the model into memory.
+    pub load: Duration,
+    /// Generating the reply.
+    pub eval: Duration,
+}
+
+/// Token counts and timings summed over several calls.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Usage {
+    pub calls: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Seconds the backend reported, summed over the calls that had timings.
+    pub total_secs: f64,
+    pub load_secs: f64,
+    pub eval_secs: f64,
+    /// Completion tokens per second of generation, over the calls that had
+    /// timings.
+    pub tokens_per_sec: Option<f64>,
+    /// Completion tokens of calls with timings, the numerator of
+    /// `tokens_per_sec`.
+    #[serde(skip)]
+    timed_tokens: usize,
+}
+
+impl Usage {
+    pub fn add(&mut self, r: &SummaryResult) {
+        self.calls += 1;
+        self.prompt_tokens += r.prompt_tokens;
+        self.completion_tokens += r.completion_tokens;
+        if let Some(t) = r.timings {
+            self.total_secs += t.total.as_secs_f64();
+            self.load_secs += t.load.as_secs_f64();
+            self.eval_secs += t.eval.as_secs_f64();
+            self.timed_tokens += r.completion_tokens;
+        }
+        self.update_rate();
+    }
+
+    pub fn merge(&mut self, other: &Usage) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_secs += other.total_secs;
+        self.load_secs += other.load_secs;
+        self.eval_secs += other.eval_secs;
+        self.timed_tokens += other.timed_tokens;
+        self.update_rate();
+    }
+
+    fn update_rate(&mut self) {
+        self.tokens_per_sec =
+            (self.eval_secs > 0.0).then(|| self.timed_tokens as f64 / self.eval_secs);
+    }
+}
+
+/// One summarization request.
+#[derive(Clone, Copy, Debug)]
+pub struct Request<'a> {
+    pub kind: PromptKind,
+    /// System message; `None` defers to the model's built-in prompt.
+    pub system: Option<&'a str>,
+    pub prompt: &'a str,
+    /// JSON schema the reply must match, e.g. [`crate::structured::SCHEMA`].
+    pub format: Option<&'a serde_json::Value>,
+    /// When to stop waiting. Streaming backends then return the output so far
+    /// as truncated; others leave timeouts to the caller.
+    pub deadline: Option<Instant>,
+}
+
+/// An LLM backend that turns a prompt into a summary.
+///
+/// Implementations retry transient failures themselves; the pipeline only
+/// bounds the total time per call.
+pub trait Summarizer: Send + Sync {
+    /// Model identifier, recorded with runs and used in summary cache keys.
+    fn model(&self) -> &str;
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>>;
+}
+
+/// Build the summarizer selected by `summarize.backend`.
+pub fn from_config(config: &Config, client: Client) -> Box<dyn Summarizer> {
+    match config.summarize.backend {
+        Backend::Ollama => Box::new(OllamaSummarizer {
+            client,
+            base_url: config.ollama.base_url.clone(),
+            model: config.ollama.model.clone(),
+            // Ollama otherwise runs at its own default window, smaller than
+            // the one prompts were sized for, and drops their start.
+            options: Options {
+                num_ctx: Some(config.context_tokens() as u32),
+                ..config.ollama.options.clone()
+            },
+            prompt_options: config.ollama.prompt_options.clone(),
+            stream: config.ollama.stream,
+            max_output_tokens: config.ollama.max_output_tokens,
+            max_elapsed: config.ollama_max_elapsed(),
+        }),
+        Backend::OpenAi => Box::new(OpenAiSummarizer {
+            client,
+            base_url: config.openai.base_url.clone(),
+            model: config.openai.model.clone(),
+            api_key: config.openai.api_key.clone(),
+            max_elapsed: Duration::from_secs(config.openai.max_elapsed_secs),
+        }),
+        Backend::LlamaCpp => Box::new(LlamaCppSummarizer {
+            client,
+            base_url: config.llamacpp.base_url.clone(),
+            model: config.llamacpp.model.clone(),
+            endpoint: config.llamacpp.endpoint,
+            params: Params {
+                n_predict: config.llamacpp.n_predict,
+                grammar: config.llamacpp.grammar.clone(),
+                json_schema: config.llamacpp.json_schema.clone(),
+            },
+            max_elapsed: Duration::from_secs(config.llamacpp.max_elapsed_secs),
+        }),
+        Backend::Mock => Box::new(MockSummarizer),
+    }
+}
+
+/// Deterministic offline backend: echoes the first prompt line as a bullet,
+/// or as headline and bullet of a structured summary when a format is
+/// requested. The system prompt is ignored.
+/// Useful for exercising the pipeline without a model server.
+pub struct MockSummarizer;
+
+impl Summarizer for MockSummarizer {
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(async move {
+            let first = req.prompt.lines().next().unwrap_or_default();
+            let text = match req.format {
+                Some(_) => serde_json::json!({
+                    "headline": first,
+                    "bullets": [first],
+                    "decisions": [],
+                    "open_questions": [],
+                    "action_items": [],
+                })
+                .to_string(),
+                None => format!("- {first}"),
+            };
+            Ok(SummaryResult {
+                prompt_tokens: count_tokens(req.prompt),
+                completion_tokens: count_tokens(&text),
+                text,
+                ..Default::default()
+            })
+        })
+    }
+}
+
+/// Approximate token count with the cl100k tokenizer.
+pub fn count_tokens(text: &str) -> usize {
+    BPE.encode_with_special_tokens(text).len()
+}
+
+/// POST `body` as JSON and decode the response, retrying transport errors,
+/// 5xx responses and undecodable bodies for at most `max_elapsed`. 4xx
+/// responses fail immediately.
+pub(crate) async fn post_json<B: Serialize, R: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    bearer: Option<&str>,
+    body: &B,
+    max_elapsed: Duration,
+) -> Result<R> {
+    send_json(
+        || {
+            let req = client.post(url).json(body);
+            match bearer {
+                Some(key) => req.bearer_auth(key),
+                None => req,
+            }
+        },
+        max_elapsed,
+    )
+    .await
+}
+
+/// GET `url` and decode the JSON response, retrying like [`post_json`].
+pub(crate) async fn get_json<R: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    max_elapsed: Duration,
+) -> Result<R> {
+    send_json(|| client.get(url), max_elapsed).await
+}
+
+/// Send the request built by `request` and return the response once its
+/// status is in, for reading a streamed body. Failures to get that far are
+/// retried like [`post_json`].
+pub(crate) async fn send_streaming(
+    request: impl Fn() -> RequestBuilder,
+    max_elapsed: Duration,
+) -> Result<Response> {
+    let backoff = ExponentialBackoff {
+        max_elapsed_time: Some(max_elapsed),
+        ..Default::default()
+    };
+    retry(backoff, || async { send(&request).await }).await
+}
+
+async fn send_json<R: DeserializeOwned>(
+    request: impl Fn() -> RequestBuilder,
+    max_elapsed: Duration,
+) -> Result<R> {
+    let backoff = ExponentialBackoff {
+        max_elapsed_time: Some(max_elapsed),
+        ..Default::default()
+    };
+    let op = || async {
+        send(&request)
+            .await?
+            .json::<R>()
+            .await
+            .map_err(|e| backoff::Error::transient(anyhow!("decode: {e:?}")))
+    };
+    retry(backoff, op).await
+}
+
+/// Send one request; 4xx responses are permanent errors, transport errors
+/// and 5xx responses transient ones.
+async fn send(
+    request: &impl Fn() -> RequestBuilder,
+) -> Result<Response, backoff::Error<anyhow::Error>> {
+    let resp = request()
+        .send()
+        .await
+        .map_err(|e| backoff::Error::transient(anyhow!("transport: {e:?}")))?;
+
+    let status = resp.status();
+    if status.is_client_error() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(backoff::Error::permanent(anyhow!("http {status}: {text}")));
+    } else if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(backoff::Error::transient(anyhow!("http {status}: {text}")));
+    }
+    Ok(resp)
+}
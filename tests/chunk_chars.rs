@@ -8,6 +8,7 @@ fn take_prefix_chars_handles_multibyte() {
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn posts_to_chunk_counts_chars() {
     let ts = OffsetDateTime::UNIX_EPOCH;
     let posts = vec![
@@ -16,12 +17,14 @@ fn posts_to_chunk_counts_chars() {
             cooked: "<p>é</p>".to_string(),
             created_at: ts,
             username: "alice".to_string(),
+            post_number: 1,
         },
         Post {
             id: 2,
             cooked: "<p>😀</p>".to_string(),
             created_at: ts,
             username: "bob".to_string(),
+            post_number: 2,
         },
     ];
     let ts_str = ts.format(&Rfc3339).unwrap();
@@ -0,0 +1,69 @@
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use zc_forum_etl::{Post, posts_to_token_chunks, summarizer::count_tokens};
+
+fn post(id: u64, text: &str) -> Post {
+    Post {
+        id,
+        cooked: format!("<p>{text}</p>"),
+        created_at: OffsetDateTime::UNIX_EPOCH,
+        username: "alice".to_string(),
+        post_number: id,
+    }
+}
+
+fn header(id: u64) -> String {
+    let ts = OffsetDateTime::UNIX_EPOCH.format(&Rfc3339).unwrap();
+    format!("[post:{id} @ {ts}]")
+}
+
+#[test]
+fn whole_posts_are_packed_within_budget() {
+    let posts = [post(1, "First."), post(2, "Second."), post(3, "Third.")];
+    let line = format!("{} Second.", header(2));
+    let max = 2 * (count_tokens(&line) + 1);
+    let chunks = posts_to_token_chunks(posts.iter(), max);
+    assert_eq!(
+        chunks,
+        [
+            format!("{} First.\n{} Second.", header(1), header(2)),
+            format!("{} Third.", header(3)),
+        ]
+    );
+    assert!(chunks.iter().all(|c| count_tokens(c) <= max));
+}
+
+#[test]
+fn oversized_posts_split_at_sentences() {
+    let sentence = "The shielded pool grew again this week.";
+    let text = [sentence; 6].join(" ");
+    let max = count_tokens(&header(1)) + 2 * count_tokens(sentence) + 4;
+    let chunks = posts_to_token_chunks([post(1, &text)].iter(), max);
+    assert!(chunks.len() > 1);
+    for chunk in &chunks {
+        assert!(count_tokens(chunk) <= max, "{chunk:?}");
+        for line in chunk.lines() {
+            let body = line.strip_prefix(&header(1)).unwrap().trim();
+            assert!(
+                body.starts_with("The") && body.ends_with("week."),
+                "{line:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn run_on_sentences_split_between_words() {
+    let text = ["word"; 200].join(" ");
+    let max = 64;
+    let chunks = posts_to_token_chunks([post(1, &text)].iter(), max);
+    let mut words = 0;
+    for chunk in &chunks {
+        assert!(count_tokens(chunk) <= max, "{chunk:?}");
+        for line in chunk.lines() {
+            let body = line.strip_prefix(&header(1)).unwrap().trim();
+            assert!(body.split(' ').all(|w| w == "word"), "{line:?}");
+            words += body.split(' ').count();
+        }
+    }
+    assert_eq!(words, 200);
+}
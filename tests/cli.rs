@@ -0,0 +1,452 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use time::OffsetDateTime;
+use wiremock::{
+    Mock, MockServer, Respond, ResponseTemplate,
+    matchers::{method, path},
+};
+use zc_forum_etl::{Post, compose_digest_item, store::Store};
+
+const FORUM: &str = "https://forum.zcashcommunity.com";
+/// Slug of the forum entry named "Zcash Forum", which keys its digest items.
+const SLUG: &str = "zcash-forum";
+
+/// Fresh working directory for one test.
+fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("zc-cli-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run the binary in `dir` with `dir/digest.toml`.
+fn digest(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_zc-forum-etl"))
+        .current_dir(dir)
+        .args(["--config", "digest.toml"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Store a summarized topic from a members-only category.
+fn store_private_topic(dir: &Path) {
+    let mut store = Store::open(&dir.join("data/digest.sqlite")).unwrap();
+    let post = Post {
+        id: 10,
+        cooked: "<p>Budget draft</p>".to_string(),
+        created_at: OffsetDateTime::now_utc(),
+        username: "alice".to_string(),
+        post_number: 1,
+    };
+    store
+        .upsert_topic(FORUM, 1, "Members only", std::slice::from_ref(&post), 1)
+        .unwrap();
+    store.set_private(FORUM, 1, true).unwrap();
+    let item = compose_digest_item(FORUM, 1, "Members only", &post, "- draft".to_string());
+    let run = store.begin_run("summarize", 24, "mock").unwrap();
+    store.save_digest_items(run, SLUG, &[item]).unwrap();
+    store.finish_run(run, "ok").unwrap();
+}
+
+fn render_into(dir: &Path, output_dir: &str, publish_private: bool) -> Output {
+    std::fs::write(
+        dir.join("digest.toml"),
+        format!(
+            "[[forums]]\nname = \"Zcash Forum\"\nbase_url = \"{FORUM}\"\noutput_dir = {output_dir:?}\npublish_private = {publish_private}\n"
+        ),
+    )
+    .unwrap();
+    digest(dir, &["render"])
+}
+
+#[test]
+fn render_refuses_private_topics_in_public_dir() {
+    let dir = workdir("private");
+    store_private_topic(&dir);
+    let absolute = dir.join("public/zcash");
+    for output_dir in ["public", absolute.to_str().unwrap(), "out/../public/zcash"] {
+        let out = render_into(&dir, output_dir, false);
+        assert!(!out.status.success(), "{output_dir} was accepted");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("refusing to publish 1 topics"), "{stderr}");
+        assert!(!dir.join("public/zcash/index.html").exists());
+        assert!(!dir.join("public/index.html").exists());
+    }
+
+    let out = render_into(&dir, "members", false);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(dir.join("members/index.html").exists());
+
+    let out = render_into(&dir, "public", true);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(dir.join("public/index.html").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn digests_of_one_forum_keep_their_own_items() {
+    let dir = workdir("filters");
+    let mut store = Store::open(&dir.join("data/digest.sqlite")).unwrap();
+    for (id, category) in [(1, "general"), (2, "grants")] {
+        let post = Post {
+            id: id * 10,
+            cooked: format!("<p>Post in {category}</p>"),
+            created_at: OffsetDateTime::now_utc(),
+            username: "alice".to_string(),
+            post_number: 1,
+        };
+        let title = format!("Topic {id}");
+        store.upsert_topic(FORUM, id, &title, &[post], 1).unwrap();
+        store.set_labels(FORUM, id, Some(category), &[]).unwrap();
+    }
+    drop(store);
+    std::fs::write(
+        dir.join("digest.toml"),
+        format!(
+            "[summarize]\nbackend = \"mock\"\nstructured = false\n\n\
+             [[forums]]\nname = \"Zcash Forum\"\nbase_url = \"{FORUM}\"\noutput_dir = \"all\"\n\n\
+             [[forums]]\nname = \"ZCG\"\nbase_url = \"{FORUM}\"\noutput_dir = \"zcg\"\n\
+             filter = {{ categories = [\"grants\"] }}\n"
+        ),
+    )
+    .unwrap();
+    for command in ["summarize", "render"] {
+        let out = digest(&dir, &[command]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+
+    let all = std::fs::read_to_string(dir.join("all/index.html")).unwrap();
+    assert!(all.contains(">Topic 1</a>") && all.contains(">Topic 2</a>"));
+    let zcg = std::fs::read_to_string(dir.join("zcg/index.html")).unwrap();
+    assert!(!zcg.contains(">Topic 1</a>") && zcg.contains(">Topic 2</a>"));
+    assert_eq!(zcg.matches(">Topic 2</a>").count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// OpenAI-compatible replies naming the topic of the prompt, slower for
+/// "Topic 1", recording when each summary request arrived and how long it
+/// was held.
+#[derive(Clone, Default)]
+struct Timed {
+    calls: Arc<Mutex<Vec<(Instant, Duration)>>>,
+}
+
+impl Respond for Timed {
+    fn respond(&self, req: &wiremock::Request) -> ResponseTemplate {
+        let body = String::from_utf8_lossy(&req.body);
+        let topic = (1..=4).find(|i| body.contains(&format!("Topic {i}")));
+        let delay = match topic {
+            Some(1) => Duration::from_millis(400),
+            Some(_) => Duration::from_millis(100),
+            None => Duration::ZERO,
+        };
+        if topic.is_some() {
+            self.calls.lock().unwrap().push((Instant::now(), delay));
+        }
+        let content = topic.map_or("- warm".to_string(), |i| format!("- about Topic {i}"));
+        ResponseTemplate::new(200)
+            .set_body_json(json!({
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": content}}]
+            }))
+            .set_delay(delay)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_summaries_keep_listing_order() {
+    let dir = workdir("concurrency");
+    let mut store = Store::open(&dir.join("data/digest.sqlite")).unwrap();
+    let now = OffsetDateTime::now_utc();
+    for id in 1..=4 {
+        let post = Post {
+            id: id * 10,
+            cooked: format!("<p>Post {id}</p>"),
+            created_at: now - time::Duration::minutes(id as i64),
+            username: "alice".to_string(),
+            post_number: 1,
+        };
+        let title = format!("Topic {id}");
+        store.upsert_topic(FORUM, id, &title, &[post], 1).unwrap();
+    }
+    let server = MockServer::start().await;
+    let timed = Timed::default();
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(timed.clone())
+        .mount(&server)
+        .await;
+    std::fs::write(
+        dir.join("digest.toml"),
+        format!(
+            "[summarize]\nbackend = \"openai\"\nstructured = false\nconcurrency = 2\n\n\
+             [openai]\nbase_url = \"{}\"\n\n\
+             [[forums]]\nname = \"Zcash Forum\"\nbase_url = \"{FORUM}\"\n",
+            server.uri()
+        ),
+    )
+    .unwrap();
+    let run_dir = dir.clone();
+    let out = tokio::task::spawn_blocking(move || digest(&run_dir, &["summarize"]))
+        .await
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // A request's reply is held for its delay, so requests whose hold
+    // windows overlap were in flight together.
+    let calls = timed.calls.lock().unwrap().clone();
+    assert_eq!(calls.len(), 4);
+    let in_flight = calls
+        .iter()
+        .map(|(at, _)| {
+            calls
+                .iter()
+                .filter(|(start, delay)| start <= at && *at < *start + *delay)
+                .count()
+        })
+        .max();
+    assert_eq!(in_flight, Some(2));
+
+    // "Topic 1" is the newest and finishes last, yet is listed first.
+    let run = store.latest_run("summarize").unwrap().unwrap();
+    let items = store.digest_items(run.id, SLUG).unwrap();
+    let titles: Vec<&str> = items.iter().map(|i| i.title.as_str()).collect();
+    assert_eq!(titles, ["Topic 1", "Topic 2", "Topic 3", "Topic 4"]);
+    for item in &items {
+        assert_eq!(item.summary, format!("- about {}", item.title));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn run_stopped_by_an_error_is_marked_failed() {
+    let dir = workdir("failed-run");
+    std::fs::write(
+        dir.join("digest.toml"),
+        format!(
+            "[[forums]]\nname = \"Zcash Forum\"\nbase_url = \"{FORUM}\"\n\
+             auth = {{ secrets_file = \"secrets/missing.toml\" }}\n"
+        ),
+    )
+    .unwrap();
+    let out = digest(&dir, &["fetch"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("reading secrets file"), "{stderr}");
+
+    let store = Store::open(&dir.join("data/digest.sqlite")).unwrap();
+    assert_eq!(store.run(1).unwrap().unwrap().status, "failed");
+    assert!(store.latest_run("fetch").unwrap().is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cut_off_structured_summary_keeps_its_headline() {
+    let dir = workdir("headline-only");
+    let mut store = Store::open(&dir.join("data/digest.sqlite")).unwrap();
+    let post = Post {
+        id: 10,
+        cooked: "<p>Shielded sync is slow.</p>".to_string(),
+        created_at: OffsetDateTime::now_utc(),
+        username: "alice".to_string(),
+        post_number: 1,
+    };
+    store.upsert_topic(FORUM, 1, "Sync", &[post], 1).unwrap();
+    let server = MockServer::start().await;
+    // Cut off at the token limit before the first bullet arrived.
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": "{\"headline\": \"Sync is slow\", \"bullets\": ["},
+                "finish_reason": "length"
+            }]
+        })))
+        .mount(&server)
+        .await;
+    std::fs::write(
+        dir.join("digest.toml"),
+        format!(
+            "[summarize]\nbackend = \"llamacpp\"\n\n\
+             [llamacpp]\nbase_url = \"{}\"\n\n\
+             [[forums]]\nname = \"Zcash Forum\"\nbase_url = \"{FORUM}\"\n",
+            server.uri()
+        ),
+    )
+    .unwrap();
+    let run_dir = dir.clone();
+    let out = tokio::task::spawn_blocking(move || digest(&run_dir, &["summarize"]))
+        .await
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let run = store.latest_run("summarize").unwrap().unwrap();
+    assert_eq!(run.status, "ok");
+    let items = store.digest_items(run.id, SLUG).unwrap();
+    assert_eq!(items[0].summary, "Sync is slow");
+    let s = items[0].structured.as_ref().unwrap();
+    assert_eq!(s.headline, "Sync is slow");
+    assert!(s.bullets.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// `/t/{id}/posts.json` replies with the requested posts of `posts`.
+struct PostsById(Vec<Post>);
+
+impl Respond for PostsById {
+    fn respond(&self, req: &wiremock::Request) -> ResponseTemplate {
+        let ids: Vec<u64> = post_ids(req);
+        let posts: Vec<&Post> = self.0.iter().filter(|p| ids.contains(&p.id)).collect();
+        ResponseTemplate::new(200).set_body_json(json!({"post_stream": {"posts": posts}}))
+    }
+}
+
+fn post_ids(req: &wiremock::Request) -> Vec<u64> {
+    req.url
+        .query_pairs()
+        .filter(|(k, _)| k == "post_ids[]")
+        .map(|(_, v)| v.parse().unwrap())
+        .collect()
+}
+
+/// `post_ids[]` batches of every `/t/1/posts.json` request so far, and the
+/// number of `/t/1.json` requests.
+async fn topic_requests(server: &MockServer) -> (Vec<Vec<u64>>, usize) {
+    let requests = server.received_requests().await.unwrap();
+    let batches = requests
+        .iter()
+        .filter(|r| r.url.path() == "/t/1/posts.json")
+        .map(post_ids)
+        .collect();
+    let topics = requests
+        .iter()
+        .filter(|r| r.url.path() == "/t/1.json")
+        .count();
+    (batches, topics)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fetch_syncs_topics_incrementally() {
+    let dir = workdir("incremental");
+    let now = OffsetDateTime::now_utc();
+    // Posts 101 to 106, #1 to #6, of which the last two are within a day.
+    let ages = [12 * 24, 9 * 24, 8 * 24, 3 * 24, 2, 1];
+    let posts: Vec<Post> = ages
+        .iter()
+        .zip(1..)
+        .map(|(hours, n)| Post {
+            id: 100 + n,
+            cooked: format!("<p>Post {n}</p>"),
+            created_at: now - time::Duration::hours(*hours),
+            username: "alice".to_string(),
+            post_number: n,
+        })
+        .collect();
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/categories.json"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({"category_list": {"categories": []}})),
+        )
+        .mount(&server)
+        .await;
+    let last_posted_at = now
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap();
+    Mock::given(method("GET"))
+        .and(path("/latest.json"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({"topic_list": {"topics": [{
+                "id": 1, "title": "Sync", "highest_post_number": 6, "last_posted_at": last_posted_at
+            }]}})),
+        )
+        .mount(&server)
+        .await;
+    // The first post comes with the topic, the rest by id.
+    Mock::given(method("GET"))
+        .and(path("/t/1.json"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({"post_stream": {
+                "posts": [&posts[0]],
+                "stream": posts.iter().map(|p| p.id).collect::<Vec<_>>()
+            }})),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/t/1/posts.json"))
+        .respond_with(PostsById(posts.clone()))
+        .mount(&server)
+        .await;
+    std::fs::write(
+        dir.join("digest.toml"),
+        format!(
+            "[fetch]\npage_size = 2\nrequests_per_sec = 100.0\nburst = 100\n\n\
+             [[forums]]\nname = \"Zcash Forum\"\nbase_url = \"{}\"\n",
+            server.uri()
+        ),
+    )
+    .unwrap();
+    let fetch = |hours: &'static str| {
+        let run_dir = dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let out = digest(&run_dir, &["fetch", "--hours", hours]);
+            assert!(
+                out.status.success(),
+                "{}",
+                String::from_utf8_lossy(&out.stderr)
+            );
+        })
+    };
+    let stored = || {
+        let store = Store::open(&dir.join("data/digest.sqlite")).unwrap();
+        let since = now - time::Duration::days(30);
+        let posts = store.posts_since(&server.uri(), 1, since).unwrap();
+        posts.iter().map(|p| p.id).collect::<Vec<_>>()
+    };
+
+    // Missing posts are requested newest first, up to the first batch that
+    // lies entirely before the window; everything fetched is kept.
+    fetch("24").await.unwrap();
+    assert_eq!(
+        topic_requests(&server).await,
+        (vec![vec![106, 105], vec![104, 103]], 1)
+    );
+    assert_eq!(stored(), [101, 103, 104, 105, 106]);
+
+    // Nothing new: the topic is not requested again.
+    fetch("24").await.unwrap();
+    assert_eq!(topic_requests(&server).await.1, 1);
+
+    // A wider window syncs the topic again for what is still missing.
+    fetch("240").await.unwrap();
+    let (batches, topics) = topic_requests(&server).await;
+    assert_eq!((&batches[2..], topics), (&[vec![102]][..], 2));
+    assert_eq!(stored(), [101, 102, 103, 104, 105, 106]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::path::Path;
+use zc_forum_etl::{
+    Forum,
+    config::Config,
+    discourse::{Credentials, Secret},
+    prompt::{PromptKind, STRUCTURED_SYSTEM_PROMPT, SYSTEM_PROMPT},
+};
+
+#[test]
+fn checked_in_config_matches_defaults() {
+    let config = Config::from_file(Path::new("digest.toml")).unwrap();
+    config.validate().unwrap();
+    let defaults = Config::default();
+    assert_eq!(config.forums, defaults.forums);
+    assert_eq!(config.digest.cutoff_hours, defaults.digest.cutoff_hours);
+    assert_eq!(
+        config.summarize.context_tokens,
+        defaults.summarize.context_tokens
+    );
+    assert_eq!(config.ollama.model, defaults.ollama.model);
+}
+
+#[test]
+fn partial_config_fills_defaults() {
+    let config = Config::from_toml(
+        "[summarize]\ntimeout_secs = 30\n\n[[forums]]\nname = \"Staging\"\nbase_url = \"https://staging.example.org/\"\n",
+    )
+    .unwrap();
+    assert_eq!(config.summarize.timeout_secs, 30);
+    assert_eq!(config.summarize.context_tokens, 8_192);
+    assert_eq!(config.forums.len(), 1);
+    assert_eq!(config.forums[0].base_url, "https://staging.example.org");
+    assert_eq!(
+        config.forums[0].output_dir,
+        Forum::default_output_dir("Staging")
+    );
+}
+
+#[test]
+fn unknown_key_is_rejected() {
+    let err = Config::from_toml("[summarize]\nchunk_max_char = 10\n").unwrap_err();
+    assert!(format!("{err:#}").contains("chunk_max_char"));
+}
+
+#[test]
+fn validation_names_bad_key() {
+    let config = Config::from_toml("[fetch]\npage_size = 0\n").unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("fetch.page_size"));
+
+    let config = Config::from_toml("[fetch]\nmax_retry_after_secs = 120\n").unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("fetch.max_retry_after_secs"));
+
+    let config = Config::from_toml("[summarize]\nconcurrency = 0\n").unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("summarize.concurrency"));
+
+    let config = Config::from_toml("[summarize]\nmax_depth = 0\n").unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("summarize.max_depth"));
+}
+
+#[test]
+fn chunk_tokens_derive_from_context_window() {
+    let config = Config::default();
+    let room = config.chunk_tokens();
+    assert!(room < 8_192 - 1_024 && room > 6_000, "{room}");
+
+    let config = Config::from_toml("[ollama.options]\nnum_ctx = 16384\n").unwrap();
+    assert_eq!(config.chunk_tokens(), room + 8_192);
+    let config = Config::from_toml("[summarize]\nchunk_max_tokens = 1000\n").unwrap();
+    assert_eq!(config.chunk_tokens(), 1_000);
+
+    let config = Config::from_toml("[summarize]\ncontext_tokens = 1024\n").unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("summarize.context_tokens"));
+}
+
+#[test]
+fn env_overrides_take_precedence() {
+    let env: HashMap<&str, &str> = HashMap::from([
+        ("LLM_MODEL", "zc-forum-summarizer"),
+        ("OLLAMA_MAX_ELAPSED_SECS", "5"),
+        ("DIGEST_MERGE_DIR", "public"),
+    ]);
+    let mut config = Config::default();
+    config
+        .apply_overrides(|k| env.get(k).map(|v| v.to_string()))
+        .unwrap();
+    assert_eq!(config.ollama.model, "zc-forum-summarizer");
+    assert_eq!(config.ollama.max_elapsed_secs, 5);
+    assert_eq!(
+        config.digest.merge_dir.as_deref(),
+        Some(Path::new("public"))
+    );
+
+    let bad: HashMap<&str, &str> = HashMap::from([("OLLAMA_MAX_ELAPSED_SECS", "soon")]);
+    let err = config
+        .apply_overrides(|k| bad.get(k).map(|v| v.to_string()))
+        .unwrap_err();
+    assert!(err.to_string().contains("OLLAMA_MAX_ELAPSED_SECS"));
+}
+
+#[test]
+fn system_prompt_can_be_replaced_or_deferred() {
+    assert_eq!(
+        Config::default().system_prompt(),
+        Some(STRUCTURED_SYSTEM_PROMPT)
+    );
+    let config = Config::from_toml("[summarize]\nstructured = false\n").unwrap();
+    assert_eq!(config.system_prompt(), Some(SYSTEM_PROMPT));
+
+    let config = Config::from_toml("[summarize]\nsystem_prompt = \"Be terse.\"\n").unwrap();
+    assert_eq!(config.system_prompt(), Some("Be terse."));
+
+    let config = Config::from_toml("[summarize]\nuse_model_system_prompt = true\n").unwrap();
+    assert_eq!(config.system_prompt(), None);
+}
+
+#[test]
+fn ollama_options_per_prompt_kind() {
+    let config = Config::from_toml(
+        "[ollama.options]\nseed = 7\ntemperature = 0.1\n\n[ollama.prompt_options.warmup]\nnum_predict = 1\n",
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.ollama.options.seed, Some(7));
+    assert_eq!(
+        config.ollama.prompt_options[&PromptKind::Warmup].num_predict,
+        Some(1)
+    );
+
+    assert!(Config::from_toml("[ollama.prompt_options.digest]\nseed = 1\n").is_err());
+    let config = Config::from_toml("[ollama.prompt_options.summary]\ntop_p = 2.0\n").unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("ollama.prompt_options.summary.top_p")
+    );
+}
+
+#[test]
+fn forum_credentials_from_config_or_secrets_file() {
+    let config = Config::from_toml(
+        "[[forums]]\nname = \"Members\"\nbase_url = \"https://forum.example.org\"\n\n[forums.auth]\napi_key = \"k1\"\napi_username = \"digest-bot\"\n",
+    )
+    .unwrap();
+    config.validate().unwrap();
+    let auth = config.forums[0].auth.as_ref().unwrap();
+    assert_eq!(
+        auth.credentials().unwrap(),
+        Credentials::ApiKey {
+            key: Secret::new("k1"),
+            username: "digest-bot".to_string()
+        }
+    );
+    // Keys never show up in debug output.
+    assert!(!format!("{config:?}").contains("k1"));
+
+    let path = std::env::temp_dir().join(format!("zc-secrets-{}.toml", std::process::id()));
+    std::fs::write(&path, "user_api_key = \"k2\"\n").unwrap();
+    let config = Config::from_toml(&format!(
+        "[[forums]]\nname = \"Members\"\nbase_url = \"https://forum.example.org\"\nauth = {{ secrets_file = {path:?} }}\n"
+    ))
+    .unwrap();
+    config.validate().unwrap();
+    let credentials = config.forums[0].auth.as_ref().unwrap().credentials();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        credentials.unwrap(),
+        Credentials::UserApiKey {
+            key: Secret::new("k2")
+        }
+    );
+
+    let config = Config::from_toml(
+        "[[forums]]\nname = \"Members\"\nbase_url = \"https://forum.example.org\"\nauth = { api_key = \"k1\" }\n",
+    )
+    .unwrap();
+    let err = config.validate().unwrap_err().to_string();
+    assert!(
+        err.contains("forums[0].auth") && err.contains("api_username"),
+        "{err}"
+    );
+    assert!(!err.contains("k1"));
+}
+
+#[test]
+fn forum_names_must_be_unique() {
+    let forum = |name: &str, filter: &str| {
+        format!(
+            "[[forums]]\nname = \"{name}\"\nbase_url = \"https://forum.example.org\"\nfilter = {{ {filter} }}\n"
+        )
+    };
+    // One forum may be digested twice with different filters.
+    let config = Config::from_toml(&format!(
+        "{}{}",
+        forum("Zcash Forum", ""),
+        forum("ZCG", "categories = [\"grants\"]")
+    ))
+    .unwrap();
+    config.validate().unwrap();
+
+    let config = Config::from_toml(&format!(
+        "{}{}",
+        forum("Zcash Forum", ""),
+        forum("zcash forum", "categories = [\"grants\"]")
+    ))
+    .unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("forums[1].name"), "{err}");
+}
@@ -0,0 +1,332 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header, method, path, query_param},
+};
+use zc_forum_etl::{
+    discourse::{
+        Category, Credentials, DEFAULT_USER_AGENT, DiscourseClient, Listing, Secret, Tag,
+        TopicStub, category_path, is_private_category,
+    },
+    http::{RateLimit, RateLimitedClient, is_status},
+};
+
+fn client(server: &MockServer) -> DiscourseClient {
+    let http = RateLimitedClient::new(
+        Client::new(),
+        RateLimit {
+            requests_per_sec: 100.0,
+            burst: 10,
+            max_requests_per_host: 100,
+            max_elapsed: Duration::from_secs(1),
+            max_retry_after: Duration::from_secs(1),
+        },
+    );
+    DiscourseClient::new(Arc::new(http), &server.uri())
+}
+
+fn topic(id: u64, last_posted_at: &str) -> Value {
+    json!({"id": id, "title": format!("Topic {id}"), "last_posted_at": last_posted_at})
+}
+
+fn post(id: u64, number: u64) -> Value {
+    json!({
+        "id": id,
+        "post_number": number,
+        "cooked": "<p>hi</p>",
+        "created_at": "2024-01-01T00:00:00Z",
+        "username": "carol"
+    })
+}
+
+async fn mount(server: &MockServer, p: &str, body: Value) {
+    Mock::given(method("GET"))
+        .and(path(p))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn latest_pages_until_cutoff() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/latest.json",
+        json!({"topic_list": {
+            "topics": [topic(1, "2024-01-03T00:00:00Z")],
+            "more_topics_url": "/latest?page=1"
+        }}),
+    )
+    .await;
+    Mock::given(method("GET"))
+        .and(path("/latest.json"))
+        .and(query_param("page", "1"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({"topic_list": {
+                "topics": [topic(2, "2023-12-01T00:00:00Z")],
+                "more_topics_url": "/latest?page=2"
+            }})),
+        )
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let c = client(&server);
+    let cutoff = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap(); // 2024-01-01
+    let topics = c.latest_since(cutoff, 5).await.unwrap();
+    let ids: Vec<u64> = topics.iter().map(|t| t.id).collect();
+    assert_eq!(ids, [1, 2]);
+    assert!(topics[1].is_stale(cutoff));
+    assert_eq!(c.requests_sent(), 2);
+
+    // The page limit stops paging even before the cutoff.
+    let c = client(&server);
+    assert_eq!(c.latest_since(cutoff, 1).await.unwrap().len(), 1);
+    assert_eq!(c.requests_sent(), 1);
+}
+
+#[test]
+fn stale_topics_have_no_posts_since_the_cutoff() {
+    let cutoff = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap(); // 2024-01-01
+    let stub = |v: Value| -> TopicStub { serde_json::from_value(v).unwrap() };
+    assert!(stub(topic(1, "2023-12-31T23:59:59Z")).is_stale(cutoff));
+    assert!(!stub(topic(1, "2024-01-01T00:00:00Z")).is_stale(cutoff));
+    // The last post wins over a later bump, e.g. from an edit.
+    assert!(
+        stub(json!({
+            "id": 1, "title": "t",
+            "last_posted_at": "2023-12-01T00:00:00Z", "bumped_at": "2024-01-02T00:00:00Z"
+        }))
+        .is_stale(cutoff)
+    );
+    assert!(
+        stub(json!({"id": 1, "title": "t", "bumped_at": "2023-12-01T00:00:00Z"})).is_stale(cutoff)
+    );
+    // Topics without timestamps are always fetched.
+    assert!(!stub(json!({"id": 1, "title": "t"})).is_stale(cutoff));
+}
+
+#[tokio::test]
+async fn topic_and_posts_by_id() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/t/7.json",
+        json!({"post_stream": {"posts": [post(70, 1)], "stream": [70, 71, 72]}}),
+    )
+    .await;
+    Mock::given(method("GET"))
+        .and(path("/t/7/posts.json"))
+        .and(query_param("post_ids[]", "72"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"post_stream": {"posts": [post(71, 2), post(72, 3)]}})),
+        )
+        .mount(&server)
+        .await;
+
+    let c = client(&server);
+    let t = c.topic(7).await.unwrap();
+    assert_eq!(t.post_stream.stream, [70, 71, 72]);
+    assert_eq!(t.post_stream.posts[0].id, 70);
+    let posts = c.posts(7, &[71, 72]).await.unwrap();
+    assert_eq!(
+        posts.iter().map(|p| p.post_number).collect::<Vec<_>>(),
+        [2, 3]
+    );
+}
+
+#[tokio::test]
+async fn categories_include_subcategories() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/categories.json",
+        json!({"category_list": {"categories": [{
+            "id": 1, "name": "General", "slug": "general", "topic_count": 12,
+            "subcategory_list": [{
+                "id": 5, "name": "Staff", "slug": "staff",
+                "parent_category_id": 1, "read_restricted": true
+            }]
+        }]}}),
+    )
+    .await;
+    let cats = client(&server).categories().await.unwrap();
+    assert_eq!(cats.len(), 2);
+    assert_eq!(
+        (cats[0].slug.as_str(), cats[0].read_restricted),
+        ("general", false)
+    );
+    assert_eq!(cats[1].parent_category_id, Some(1));
+    assert!(cats[1].read_restricted);
+}
+
+#[test]
+fn unknown_categories_are_private_when_authenticated() {
+    let cats: Vec<Category> = serde_json::from_value(json!([
+        {"id": 1, "name": "General", "slug": "general"},
+        {"id": 5, "name": "Staff", "slug": "staff", "read_restricted": true}
+    ]))
+    .unwrap();
+    assert!(!is_private_category(&cats, Some(1), true));
+    assert!(is_private_category(&cats, Some(5), false));
+    // Anonymous requests never see members-only topics.
+    assert!(!is_private_category(&cats, Some(9), false));
+    assert!(!is_private_category(&cats, None, false));
+    assert!(is_private_category(&cats, Some(9), true));
+    assert!(is_private_category(&cats, None, true));
+}
+
+#[tokio::test]
+async fn category_and_tag_listings() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/categories.json",
+        json!({"category_list": {"categories": [{
+            "id": 3, "name": "Grants", "slug": "grants",
+            "subcategory_list": [{"id": 8, "name": "Updates", "slug": "zcg-updates", "parent_category_id": 3}]
+        }]}}),
+    )
+    .await;
+    mount(
+        &server,
+        "/c/grants/3/l/latest.json",
+        json!({"topic_list": {"topics": [topic(1, "2024-01-03T00:00:00Z")]}}),
+    )
+    .await;
+    mount(
+        &server,
+        "/tag/zips.json",
+        json!({"topic_list": {"topics": [topic(2, "2024-01-03T00:00:00Z")]}}),
+    )
+    .await;
+
+    let c = client(&server);
+    let cats = c.categories().await.unwrap();
+    assert_eq!(
+        category_path(&cats, 8).as_deref(),
+        Some("grants/zcg-updates")
+    );
+    assert_eq!(category_path(&cats, 3).as_deref(), Some("grants"));
+    assert_eq!(category_path(&cats, 99), None);
+
+    let cutoff = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap(); // 2024-01-01
+    let grants = c
+        .listing_since(&Listing::Category(&cats[0]), cutoff, 5)
+        .await
+        .unwrap();
+    assert_eq!(grants[0].id, 1);
+    let zips = c
+        .listing_since(&Listing::Tag("zips"), cutoff, 5)
+        .await
+        .unwrap();
+    assert_eq!(zips[0].id, 2);
+}
+
+#[tokio::test]
+async fn tags_in_old_and_new_formats() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/tags.json",
+        json!({"tags": [
+            {"id": "zsa", "text": "zsa", "count": 4},
+            {"id": 3, "name": "nu6", "text": "nu6", "count": 2}
+        ]}),
+    )
+    .await;
+    let tags = client(&server).tags().await.unwrap();
+    assert_eq!(
+        tags,
+        [
+            Tag {
+                name: "zsa".into(),
+                count: 4
+            },
+            Tag {
+                name: "nu6".into(),
+                count: 2
+            }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn user_profile() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/u/carol.json",
+        json!({"user": {"id": 9, "username": "carol", "name": "Carol", "trust_level": 2}}),
+    )
+    .await;
+    let u = client(&server).user("carol").await.unwrap();
+    assert_eq!((u.id, u.username.as_str(), u.trust_level), (9, "carol", 2));
+}
+
+#[tokio::test]
+async fn sends_api_key_and_user_agent() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/tags.json"))
+        .and(header("api-key", "secret"))
+        .and(header("api-username", "system"))
+        .and(header("user-agent", "digest-bot/1.0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"tags": []})))
+        .mount(&server)
+        .await;
+    let credentials = Credentials::ApiKey {
+        key: Secret::new("secret"),
+        username: "system".to_string(),
+    };
+    let c = client(&server)
+        .with_credentials(&credentials)
+        .unwrap()
+        .with_user_agent("digest-bot/1.0")
+        .unwrap();
+    assert!(c.tags().await.unwrap().is_empty());
+
+    // Without a key the request does not match, so the client gives up.
+    assert!(client(&server).tags().await.is_err());
+    let requests = server.received_requests().await.unwrap();
+    let last = requests.last().unwrap();
+    assert_eq!(last.headers["user-agent"], DEFAULT_USER_AGENT);
+    assert!(!last.headers.contains_key("api-key"));
+}
+
+#[tokio::test]
+async fn user_api_key_without_scope_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/categories.json"))
+        .and(header("user-api-key", "limited"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(
+            json!({"errors": ["You are not permitted to view the requested resource."], "error_type": "invalid_access"}),
+        ))
+        .mount(&server)
+        .await;
+    let credentials = Credentials::UserApiKey {
+        key: Secret::new("limited"),
+    };
+    let err = client(&server)
+        .with_credentials(&credentials)
+        .unwrap()
+        .categories()
+        .await
+        .unwrap_err();
+    let msg = format!("{err:#}");
+    assert!(
+        msg.contains("user API key") && msg.contains("scope"),
+        "{msg}"
+    );
+    assert!(!msg.contains("limited"));
+    assert!(is_status(&err, StatusCode::FORBIDDEN));
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
@@ -0,0 +1,61 @@
+use zc_forum_etl::config::{Config, ForumFilter};
+
+fn tags(names: &[&str]) -> Vec<String> {
+    names.iter().map(|t| t.to_string()).collect()
+}
+
+fn filter(toml: &str) -> ForumFilter {
+    let config = Config::from_toml(&format!(
+        "[[forums]]\nname = \"Zcash Forum\"\nbase_url = \"https://forum.zcashcommunity.com\"\n\n[forums.filter]\n{toml}"
+    ))
+    .unwrap();
+    config.validate().unwrap();
+    config.forums[0].filter.clone()
+}
+
+#[test]
+fn empty_filter_allows_everything() {
+    let f = ForumFilter::default();
+    assert!(f.allows(1, None, &[]));
+    assert!(f.allows(2, Some("general"), &tags(&["nu6"])));
+}
+
+#[test]
+fn allow_lists_match_any_of_category_tag_or_id() {
+    let f = filter("categories = [\"grants\"]\ntags = [\"zips\"]\ntopics = [42]\n");
+    assert!(f.allows(1, Some("grants"), &[]));
+    // Subcategories follow their parent.
+    assert!(f.allows(2, Some("grants/zcg-updates"), &[]));
+    assert!(f.allows(3, Some("protocol"), &tags(&["zips", "nu7"])));
+    assert!(f.allows(42, Some("general"), &[]));
+    assert!(!f.allows(4, Some("general"), &tags(&["nu7"])));
+    assert!(!f.allows(5, None, &[]));
+}
+
+#[test]
+fn deny_lists_win() {
+    let f = filter(
+        "categories = [\"grants\"]\nexclude_categories = [\"zcg-updates\"]\nexclude_tags = [\"spam\"]\nexclude_topics = [7]\n",
+    );
+    assert!(!f.allows(1, Some("grants/zcg-updates"), &[]));
+    assert!(!f.allows(2, Some("grants"), &tags(&["spam"])));
+    assert!(!f.allows(7, Some("grants"), &[]));
+    assert!(f.allows(8, Some("grants"), &[]));
+
+    let f = filter("exclude_categories = [\"off-topic\"]\n");
+    assert!(f.allows(1, Some("general"), &[]));
+    assert!(!f.allows(2, Some("off-topic"), &[]));
+}
+
+#[test]
+fn listings_need_something_to_list() {
+    let config = Config::from_toml(
+        "[[forums]]\nname = \"Zcash Forum\"\nbase_url = \"https://forum.zcashcommunity.com\"\nfilter = { use_listings = true, topics = [1] }\n",
+    )
+    .unwrap();
+    let err = config.validate().unwrap_err();
+    assert!(
+        err.to_string().contains("forums[0].filter.use_listings"),
+        "{err}"
+    );
+}
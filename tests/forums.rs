@@ -0,0 +1,35 @@
+use std::path::{Path, PathBuf};
+use zc_forum_etl::{DEFAULT_FORUM_URL, Forum, is_public_dir, parse_forums};
+
+#[test]
+fn parses_forum_specs() {
+    let forums = parse_forums(
+        "Zcash Forum|https://forum.zcashcommunity.com/|public; ZF Staging|https://staging.example.org",
+    )
+    .unwrap();
+    assert_eq!(forums.len(), 2);
+    assert_eq!(forums[0], Forum::default());
+    assert_eq!(forums[0].base_url, DEFAULT_FORUM_URL);
+    assert_eq!(forums[1].name, "ZF Staging");
+    assert_eq!(forums[1].output_dir, PathBuf::from("public/zf-staging"));
+}
+
+#[test]
+fn rejects_invalid_forum_specs() {
+    assert!(parse_forums("").is_err());
+    assert!(parse_forums("No URL").is_err());
+    assert!(parse_forums("Bad|ftp://example.org").is_err());
+}
+
+#[test]
+fn recognizes_public_output_dirs() {
+    let cwd = std::env::current_dir().unwrap();
+    assert!(is_public_dir(Path::new("public")).unwrap());
+    assert!(is_public_dir(Path::new("./public/.")).unwrap());
+    assert!(is_public_dir(&cwd.join("public")).unwrap());
+    assert!(is_public_dir(Path::new("src/../public")).unwrap());
+    assert!(!is_public_dir(Path::new("src")).unwrap());
+    assert!(!is_public_dir(&std::env::temp_dir()).unwrap());
+    // A path that cannot be resolved is not assumed to be private.
+    assert!(is_public_dir(Path::new("no-such-dir/../public")).is_err());
+}
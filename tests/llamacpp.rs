@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde_json::json;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{body_partial_json, method, path},
+};
+use zc_forum_etl::{
+    config::Config,
+    llamacpp::{Endpoint, Params, summarize_with_llamacpp},
+    prompt::PromptKind,
+    summarizer::Request,
+};
+
+const PROMPT: &str = "Thread: test\n\nContent excerpt:\n---\nHello world\n---";
+
+#[tokio::test]
+async fn completion_sends_n_predict_and_grammar() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/completion"))
+        .and(body_partial_json(json!({
+            "prompt": PROMPT,
+            "n_predict": 128,
+            "grammar": "root ::= \"- \" [a-z]+",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "content": "- hello",
+            "tokens_evaluated": 21,
+            "tokens_predicted": 128,
+            "stop": true,
+            "stop_type": "limit",
+            "timings": {"prompt_n": 21, "prompt_ms": 50.0, "predicted_n": 128, "predicted_ms": 1950.0}
+        })))
+        .mount(&server)
+        .await;
+    let params = Params {
+        n_predict: 128,
+        grammar: Some("root ::= \"- \" [a-z]+".to_string()),
+        json_schema: None,
+    };
+    let r = summarize_with_llamacpp(
+        &Client::new(),
+        &server.uri(),
+        Endpoint::Completion,
+        "llama-server",
+        Request {
+            kind: PromptKind::Summary,
+            system: None,
+            prompt: PROMPT,
+            format: None,
+            deadline: None,
+        },
+        &params,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(r.text, "- hello");
+    assert_eq!((r.prompt_tokens, r.completion_tokens), (21, 128));
+    // Stopped by n_predict.
+    assert!(r.truncated);
+    let timings = r.timings.unwrap();
+    assert_eq!(timings.total, Duration::from_secs(2));
+    assert_eq!(timings.eval, Duration::from_millis(1950));
+}
+
+#[tokio::test]
+async fn chat_sends_json_schema_and_max_tokens() {
+    let schema = json!({"type": "object", "properties": {"bullets": {"type": "array"}}});
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(body_partial_json(
+            json!({"max_tokens": 64, "json_schema": schema}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": "{\"bullets\":[]}"},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 30, "completion_tokens": 6, "total_tokens": 36}
+        })))
+        .mount(&server)
+        .await;
+    let params = Params {
+        n_predict: 64,
+        grammar: None,
+        json_schema: Some(schema.clone()),
+    };
+    let r = summarize_with_llamacpp(
+        &Client::new(),
+        &server.uri(),
+        Endpoint::Chat,
+        "llama-server",
+        Request {
+            kind: PromptKind::Summary,
+            system: None,
+            prompt: PROMPT,
+            format: None,
+            deadline: None,
+        },
+        &params,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(r.text, "{\"bullets\":[]}");
+    assert_eq!((r.prompt_tokens, r.completion_tokens), (30, 6));
+    assert!(!r.truncated);
+    assert_eq!(r.timings, None);
+}
+
+#[tokio::test]
+async fn client_error_is_permanent() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/completion"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+    let params = Params {
+        n_predict: -1,
+        ..Default::default()
+    };
+    let res = summarize_with_llamacpp(
+        &Client::new(),
+        &server.uri(),
+        Endpoint::Completion,
+        "llama-server",
+        Request {
+            kind: PromptKind::Summary,
+            system: None,
+            prompt: PROMPT,
+            format: None,
+            deadline: None,
+        },
+        &params,
+        Duration::from_secs(1),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[test]
+fn grammar_and_schema_are_exclusive() {
+    let config = Config::from_toml(
+        "[llamacpp]\nendpoint = \"completion\"\ngrammar = 'root ::= \"x\"'\njson_schema = { type = \"string\" }\n",
+    )
+    .unwrap();
+    assert_eq!(config.llamacpp.endpoint, Endpoint::Completion);
+    let err = config.validate().unwrap_err();
+    assert!(err.to_string().contains("llamacpp.grammar"));
+}
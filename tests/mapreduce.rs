@@ -0,0 +1,240 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use time::OffsetDateTime;
+use zc_forum_etl::{
+    Post,
+    config::Config,
+    mapreduce::{chunk_budget, summarize_thread, summarize_thread_cached, within_budget},
+    posts_to_token_chunks,
+    prompt::PromptKind,
+    store::Store,
+    structured::StructuredSummary,
+    summarizer::{MockSummarizer, Request, Summarizer, SummaryResult, count_tokens},
+};
+
+/// Replies `- <kind> <n>` and records the kind of every call.
+#[derive(Default)]
+struct Recorder {
+    kinds: Mutex<Vec<PromptKind>>,
+}
+
+impl Summarizer for Recorder {
+    fn model(&self) -> &str {
+        "recorder"
+    }
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(async move {
+            let mut kinds = self.kinds.lock().unwrap();
+            kinds.push(req.kind);
+            Ok(SummaryResult {
+                text: format!("- {} {}", req.kind.as_str(), kinds.len()),
+                ..Default::default()
+            })
+        })
+    }
+}
+
+/// Replies with `text`, cut off as if the deadline had passed.
+struct CutOff(&'static str);
+
+impl Summarizer for CutOff {
+    fn model(&self) -> &str {
+        "cut-off"
+    }
+
+    fn summarize<'a>(&'a self, _req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(async move {
+            Ok(SummaryResult {
+                text: self.0.to_string(),
+                truncated: true,
+                ..Default::default()
+            })
+        })
+    }
+}
+
+fn config(toml: &str) -> Config {
+    let config = Config::from_toml(toml).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+fn chunks(n: usize) -> Vec<String> {
+    (1..=n).map(|i| format!("[post:{i}] part {i}")).collect()
+}
+
+/// Chunk budget holding two partial summaries of the `Recorder`, title included.
+fn two_summaries() -> usize {
+    2 * (count_tokens("- summary 1") + 1) + count_tokens("t")
+}
+
+#[tokio::test]
+async fn single_chunk_is_summarized_directly() {
+    let config = config("[summarize]\nstructured = false\n");
+    let r = Recorder::default();
+    let out = summarize_thread(&r, &config, "t", &chunks(1))
+        .await
+        .unwrap();
+    assert_eq!(out.raw, "- summary 1");
+    assert!(!out.truncated);
+    assert_eq!(*r.kinds.lock().unwrap(), [PromptKind::Summary]);
+}
+
+#[tokio::test]
+async fn long_threads_are_reduced_level_by_level() {
+    let config = config(&format!(
+        "[summarize]\nstructured = false\nchunk_max_tokens = {}\nmax_depth = 3\n",
+        two_summaries()
+    ));
+    let r = Recorder::default();
+    let out = summarize_thread(&r, &config, "t", &chunks(4))
+        .await
+        .unwrap();
+    use PromptKind::{Reduce, Summary};
+    assert_eq!(
+        *r.kinds.lock().unwrap(),
+        [Summary, Summary, Summary, Summary, Reduce, Reduce, Reduce]
+    );
+    assert_eq!(out.raw, "- reduce 7");
+}
+
+#[tokio::test]
+async fn last_level_combines_everything_left() {
+    let config = config(&format!(
+        "[summarize]\nstructured = false\nchunk_max_tokens = {}\nmax_depth = 1\n",
+        two_summaries()
+    ));
+    let r = Recorder::default();
+    summarize_thread(&r, &config, "t", &chunks(5))
+        .await
+        .unwrap();
+    let kinds = r.kinds.lock().unwrap();
+    assert_eq!(kinds.len(), 6);
+    assert_eq!(kinds[5], PromptKind::Reduce);
+}
+
+#[tokio::test]
+async fn structured_reduce_returns_json() {
+    let config = config("");
+    let out = summarize_thread(&MockSummarizer, &config, "t", &chunks(3))
+        .await
+        .unwrap();
+    let s = StructuredSummary::parse(&out.raw).unwrap();
+    assert!(s.headline.starts_with("Thread: t"));
+}
+
+#[test]
+fn budget_drops_oldest_chunks_after_the_first() {
+    let all = chunks(4);
+    let per_chunk = count_tokens(&all[0]);
+    assert!(all.iter().all(|c| count_tokens(c) == per_chunk));
+    assert_eq!(
+        within_budget(all.clone(), per_chunk * 2),
+        [all[0].clone(), all[3].clone()]
+    );
+    assert_eq!(
+        within_budget(all.clone(), per_chunk * 3),
+        [all[0].clone(), all[2].clone(), all[3].clone()]
+    );
+    // The first chunk survives even a budget it exceeds.
+    assert_eq!(within_budget(all.clone(), 1), all[..1]);
+    assert!(within_budget(Vec::new(), 1).is_empty());
+}
+
+#[test]
+fn default_budget_keeps_multi_chunk_threads() {
+    let config = config("");
+    let posts: Vec<Post> = (1..=6)
+        .map(|id| Post {
+            id,
+            cooked: format!("<p>{}</p>", "Shielded sync is slow. ".repeat(600)),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            username: "alice".to_string(),
+            post_number: id,
+        })
+        .collect();
+    let all = posts_to_token_chunks(posts.iter(), chunk_budget(&config, "t"));
+    assert!(all.len() > 2, "{} chunks", all.len());
+    assert!(config.token_budget() > config.chunk_tokens() * 2);
+    assert_eq!(within_budget(all.clone(), config.token_budget()), all);
+}
+
+#[tokio::test]
+async fn cut_off_output_is_kept_as_truncated() {
+    let config = config("[summarize]\nstructured = false\ntimeout_secs = 7\n");
+    let out = summarize_thread(&CutOff("- partial"), &config, "t", &chunks(2))
+        .await
+        .unwrap();
+    assert_eq!(out.raw, "- partial");
+    assert!(out.truncated);
+
+    let err = summarize_thread(&CutOff(""), &config, "t", &chunks(1))
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "timed out after 7s");
+
+    // Truncated JSON is not asked for again; the fields that arrived are kept.
+    let config = self::config("[summarize]\ntimeout_secs = 7\n");
+    let out = summarize_thread(
+        &CutOff("{\"headline\": \"Sync\", \"bullets\": [\"slow\", \"v6"),
+        &config,
+        "t",
+        &chunks(1),
+    )
+    .await
+    .unwrap();
+    assert!(out.truncated);
+    let s = StructuredSummary::parse(&out.raw).unwrap();
+    assert_eq!((s.headline.as_str(), s.bullets.len()), ("Sync", 2));
+
+    let err = summarize_thread(&CutOff("{\"head"), &config, "t", &chunks(1))
+        .await
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("cut off"), "{err:#}");
+}
+
+#[tokio::test]
+async fn cut_off_before_any_bullet_keeps_the_headline() {
+    let config = config("[summarize]\ntimeout_secs = 7\n");
+    let reply = CutOff("{\"headline\": \"Sync is slow\", \"bullets\": [");
+    let out = summarize_thread(&reply, &config, "t", &chunks(1))
+        .await
+        .unwrap();
+    assert!(out.truncated);
+    let s = out.structured.unwrap();
+    assert_eq!(s.headline, "Sync is slow");
+    assert!(s.bullets.is_empty());
+}
+
+#[tokio::test]
+async fn calls_are_cached_chunk_by_chunk() {
+    let config = config(&format!(
+        "[summarize]\nstructured = false\nchunk_max_tokens = {}\nmax_depth = 3\n",
+        two_summaries()
+    ));
+    let store = Store::open_in_memory().unwrap();
+    let r = Recorder::default();
+    let out = summarize_thread_cached(&r, &config, Some(&store), "t", &chunks(2))
+        .await
+        .unwrap();
+    assert_eq!((out.usage.calls, out.cache_hits), (3, 0));
+
+    // Same thread: every call comes from the cache.
+    let again = summarize_thread_cached(&r, &config, Some(&store), "t", &chunks(2))
+        .await
+        .unwrap();
+    assert_eq!((again.usage.calls, again.cache_hits), (0, 3));
+    assert_eq!(again.raw, out.raw);
+
+    // A new chunk is summarized, the old ones are not, and the summaries are
+    // combined again.
+    let more = summarize_thread_cached(&r, &config, Some(&store), "t", &chunks(3))
+        .await
+        .unwrap();
+    assert_eq!((more.usage.calls, more.cache_hits), (2, 2));
+    use PromptKind::{Reduce, Summary};
+    assert_eq!(r.kinds.lock().unwrap()[3..], [Summary, Reduce]);
+}
@@ -1,5 +1,5 @@
 use std::fs;
-use zc_forum_etl::{Post, compose_digest_item};
+use zc_forum_etl::{DigestItem, Post, compose_digest_item};
 
 #[test]
 fn merges_metadata_with_summary() {
@@ -42,3 +42,22 @@ fn merges_metadata_with_summary() {
     assert_eq!(item2.created_at, post.created_at);
     assert_eq!(item2.summary, fake);
 }
+
+#[test]
+fn digest_item_round_trips_through_json() {
+    let data = fs::read_to_string("tests/fixtures/post.json").unwrap();
+    let post: Post = serde_json::from_str(&data).unwrap();
+    let item = compose_digest_item(
+        "https://forum.zcashcommunity.com",
+        42,
+        "Example Topic",
+        &post,
+        "- summary".to_string(),
+    );
+    let json = serde_json::to_string(&item).unwrap();
+    assert!(json.contains("\"created_at\":\"2024-01-01T00:00:00Z\""));
+    let back: DigestItem = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.created_at, item.created_at);
+    assert_eq!(back.url, item.url);
+    assert_eq!(back.summary, item.summary);
+}
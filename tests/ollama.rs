@@ -1,9 +1,20 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use reqwest::Client;
 use wiremock::{
     Mock, MockServer, ResponseTemplate,
-    matchers::{method, path},
+    matchers::{body_partial_json, method, path},
+};
+use zc_forum_etl::{
+    config::Config,
+    ollama::{self, ModelInfo, Modelfile, OllamaSummarizer, Options},
+    prompt::{PromptKind, SYSTEM_PROMPT},
+    summarize_with_ollama,
+    summarizer::{self, Request, Summarizer},
 };
-use zc_forum_etl::summarize_with_ollama;
+
+const MAX_ELAPSED: Duration = Duration::from_secs(1);
 
 #[tokio::test]
 async fn summarize_ollama_local() {
@@ -14,19 +25,28 @@ async fn summarize_ollama_local() {
     let model = std::env::var("OLLAMA_TEST_MODEL").unwrap_or_else(|_| "qwen2.5:latest".to_string());
     let prompt = "Thread: test\n\nContent excerpt:\n---\nHello world\n---";
     let client = Client::new();
-    let (summary, in_tok, out_tok) = summarize_with_ollama(&client, &base, &model, prompt)
+    let max_elapsed = Config::default().ollama_max_elapsed();
+    let r = summarize_with_ollama(&client, &base, &model, prompt, max_elapsed)
         .await
         .expect("ollama call");
-    assert!(!summary.is_empty());
-    assert!(in_tok > 0);
-    assert!(out_tok > 0);
+    assert!(!r.text.is_empty());
+    assert!(r.prompt_tokens > 0);
+    assert!(r.completion_tokens > 0);
+    assert!(r.timings.is_some());
 }
 
 #[tokio::test]
 async fn summarize_ollama_retry_error() {
     let client = Client::new();
     let prompt = "Thread: test\n\nContent excerpt:\n---\nHello world\n---";
-    let res = summarize_with_ollama(&client, "http://127.0.0.1:1", "test-model", prompt).await;
+    let res = summarize_with_ollama(
+        &client,
+        "http://127.0.0.1:1",
+        "test-model",
+        prompt,
+        MAX_ELAPSED,
+    )
+    .await;
     assert!(res.is_err());
 }
 
@@ -40,7 +60,8 @@ async fn summarize_ollama_client_error_is_permanent() {
         .await;
     let client = Client::new();
     let prompt = "Thread: test\n\nContent excerpt:\n---\nHello world\n---";
-    let res = summarize_with_ollama(&client, &server.uri(), "test-model", prompt).await;
+    let res =
+        summarize_with_ollama(&client, &server.uri(), "test-model", prompt, MAX_ELAPSED).await;
     assert!(res.is_err());
     let requests = server.received_requests().await.unwrap();
     assert_eq!(requests.len(), 1);
@@ -56,14 +77,379 @@ async fn summarize_ollama_server_error_is_transient() {
         .await;
     let client = Client::new();
     let prompt = "Thread: test\n\nContent excerpt:\n---\nHello world\n---";
-    unsafe {
-        std::env::set_var("OLLAMA_MAX_ELAPSED_SECS", "1");
-    }
-    let res = summarize_with_ollama(&client, &server.uri(), "test-model", prompt).await;
-    unsafe {
-        std::env::remove_var("OLLAMA_MAX_ELAPSED_SECS");
-    }
+    let res =
+        summarize_with_ollama(&client, &server.uri(), "test-model", prompt, MAX_ELAPSED).await;
     assert!(res.is_err());
     let requests = server.received_requests().await.unwrap();
     assert!(requests.len() > 1);
 }
+
+#[tokio::test]
+async fn summarize_ollama_sends_system_prompt() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!({"message": {"role": "assistant", "content": "- ok"}}),
+        ))
+        .mount(&server)
+        .await;
+    let client = Client::new();
+    let prompt = "Thread: test\n\nContent excerpt:\n---\nHello world\n---";
+    let r = summarize_with_ollama(&client, &server.uri(), "test-model", prompt, MAX_ELAPSED)
+        .await
+        .unwrap();
+    assert_eq!(r.text, "- ok");
+    let requests = server.received_requests().await.unwrap();
+    let body: serde_json::Value = requests[0].body_json().unwrap();
+    assert_eq!(body["messages"][0]["role"], "system");
+    assert_eq!(body["messages"][0]["content"], SYSTEM_PROMPT);
+    assert_eq!(body["messages"][1]["content"], prompt);
+}
+
+#[tokio::test]
+async fn ollama_summarizer_sends_options_per_prompt_kind() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!({"message": {"role": "assistant", "content": "- ok"}}),
+        ))
+        .mount(&server)
+        .await;
+    let summarizer = OllamaSummarizer {
+        client: Client::new(),
+        base_url: server.uri(),
+        model: "test-model".to_string(),
+        options: Options {
+            temperature: Some(0.2),
+            seed: Some(42),
+            ..Default::default()
+        },
+        prompt_options: HashMap::from([(
+            PromptKind::Warmup,
+            Options {
+                num_predict: Some(1),
+                ..Default::default()
+            },
+        )]),
+        stream: false,
+        max_output_tokens: None,
+        max_elapsed: Duration::from_secs(1),
+    };
+    for kind in [PromptKind::Warmup, PromptKind::Summary] {
+        let req = Request {
+            kind,
+            system: None,
+            prompt: "Hello world",
+            format: None,
+            deadline: None,
+        };
+        summarizer.summarize(req).await.unwrap();
+    }
+
+    let requests = server.received_requests().await.unwrap();
+    let warmup: serde_json::Value = requests[0].body_json().unwrap();
+    assert_eq!(
+        warmup["options"],
+        serde_json::json!({"temperature": 0.2, "seed": 42, "num_predict": 1})
+    );
+    let summary: serde_json::Value = requests[1].body_json().unwrap();
+    assert_eq!(
+        summary["options"],
+        serde_json::json!({"temperature": 0.2, "seed": 42})
+    );
+    assert_eq!(summary["messages"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn model_is_looked_up_in_tags() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/tags"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "models": [{"name": "zc-forum-summarizer:latest", "size": 1}]
+        })))
+        .mount(&server)
+        .await;
+    let client = Client::new();
+    let secs = Duration::from_secs(1);
+    assert!(
+        ollama::has_model(&client, &server.uri(), "zc-forum-summarizer", secs)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !ollama::has_model(&client, &server.uri(), "qwen2.5:latest", secs)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn show_sizes_context_window() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/show"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "parameters": "num_ctx                        8192\nstop                           \"<|im_end|>\"",
+            "model_info": {"general.architecture": "qwen2", "qwen2.context_length": 32768}
+        })))
+        .mount(&server)
+        .await;
+    let info = ollama::show_model(
+        &Client::new(),
+        &server.uri(),
+        "zc-forum-summarizer",
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(info.context_length, Some(32_768));
+    assert_eq!(info.num_ctx(), Some(8_192));
+    assert_eq!(info.parameters[1], ("stop".into(), "\"<|im_end|>\"".into()));
+
+    let mut config = Config::from_toml("[summarize]\ncontext_tokens = 4096\n").unwrap();
+    config.apply_model_info(&info);
+    assert_eq!(config.context_tokens(), 8_192);
+    // Without a Modelfile num_ctx the configured window stays, capped by training.
+    config.apply_model_info(&ModelInfo {
+        context_length: Some(2_048),
+        parameters: Vec::new(),
+    });
+    assert_eq!(config.context_tokens(), 2_048);
+}
+
+#[tokio::test]
+async fn requests_carry_the_context_prompts_were_sized_for() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!({"message": {"role": "assistant", "content": "- ok"}}),
+        ))
+        .mount(&server)
+        .await;
+    // No Modelfile num_ctx: chunks are sized for the configured window, which
+    // Ollama only uses if the request asks for it.
+    let mut config = Config::from_toml("[summarize]\ncontext_tokens = 8192\n").unwrap();
+    config.apply_model_info(&ModelInfo {
+        context_length: Some(32_768),
+        parameters: Vec::new(),
+    });
+    config.ollama.base_url = server.uri();
+    config.ollama.stream = false;
+    let summarizer = summarizer::from_config(&config, Client::new());
+    let req = Request {
+        kind: PromptKind::Summary,
+        system: None,
+        prompt: "Hello world",
+        format: None,
+        deadline: None,
+    };
+    summarizer.summarize(req).await.unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    let body: serde_json::Value = requests[0].body_json().unwrap();
+    assert_eq!(body["options"]["num_ctx"], 8_192);
+    assert_eq!(body["options"]["num_ctx"], config.context_tokens());
+    assert!(config.chunk_tokens() < config.context_tokens());
+}
+
+#[test]
+fn repository_modelfile_parses() {
+    let m = Modelfile::parse(&std::fs::read_to_string("Modelfile").unwrap()).unwrap();
+    assert_eq!(m.from, "qwen2.5:latest");
+    assert!(
+        m.system
+            .unwrap()
+            .starts_with("You are summarizing ONE forum thread")
+    );
+    assert!(m.parameters.contains(&("num_ctx".into(), "8192".into())));
+
+    assert!(Modelfile::parse("SYSTEM hi\n").is_err());
+    assert!(Modelfile::parse("FROM x\nADAPTER ./lora\n").is_err());
+}
+
+#[tokio::test]
+async fn bootstrap_pulls_base_then_creates() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/tags"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"models": []})))
+        .mount(&server)
+        .await;
+    let pull = concat!(
+        r#"{"status":"pulling manifest"}"#,
+        "\n",
+        r#"{"status":"pulling abc","digest":"abc","total":100,"completed":50}"#,
+        "\n",
+        r#"{"status":"success"}"#,
+        "\n"
+    );
+    Mock::given(method("POST"))
+        .and(path("/api/pull"))
+        .and(body_partial_json(
+            serde_json::json!({"model": "qwen2.5:latest"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_string(pull))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/create"))
+        .and(body_partial_json(serde_json::json!({
+            "model": "zc-forum-summarizer",
+            "from": "qwen2.5:latest",
+            "parameters": {"num_ctx": 8192, "temperature": 0.2, "stop": ["<|im_end|>"]}
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"status\":\"success\"}\n"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let modelfile = Modelfile::parse(
+        "FROM qwen2.5:latest\nPARAMETER num_ctx 8192\nPARAMETER temperature 0.2\nPARAMETER stop \"<|im_end|>\"\n",
+    )
+    .unwrap();
+    ollama::bootstrap(
+        &Client::new(),
+        &server.uri(),
+        "zc-forum-summarizer",
+        Some(&modelfile),
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn bootstrap_reports_stream_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/pull"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "{\"status\":\"pulling manifest\"}\n{\"error\":\"pull model manifest: file does not exist\"}\n",
+        ))
+        .mount(&server)
+        .await;
+    let err = ollama::bootstrap(
+        &Client::new(),
+        &server.uri(),
+        "no-such-model",
+        None,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("file does not exist"), "{err:#}");
+}
+
+fn streaming(base_url: String, max_output_tokens: Option<usize>) -> OllamaSummarizer {
+    OllamaSummarizer {
+        client: Client::new(),
+        base_url,
+        model: "test-model".to_string(),
+        options: Options::default(),
+        prompt_options: HashMap::new(),
+        stream: true,
+        max_output_tokens,
+        max_elapsed: Duration::from_secs(1),
+    }
+}
+
+/// NDJSON reply of three chunks and the final stats, one line each.
+fn stream_lines() -> String {
+    ["- Sync", " is", " slow"]
+        .iter()
+        .map(|t| serde_json::json!({"message": {"role": "assistant", "content": t}, "done": false}))
+        .chain([serde_json::json!({
+            "done": true, "done_reason": "stop",
+            "prompt_eval_count": 26, "eval_count": 3,
+            "total_duration": 2_500_000_000u64, "load_duration": 500_000_000u64,
+            "eval_duration": 1_500_000_000u64
+        })])
+        .map(|l| format!("{l}\n"))
+        .collect()
+}
+
+async fn mount_stream(server: &MockServer, delay: Duration) {
+    mount_stream_body(server, stream_lines(), delay).await;
+}
+
+async fn mount_stream_body(server: &MockServer, lines: String, delay: Duration) {
+    Mock::given(method("POST"))
+        .and(path("/api/chat"))
+        .and(body_partial_json(serde_json::json!({"stream": true})))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(lines)
+                .set_delay(delay),
+        )
+        .mount(server)
+        .await;
+}
+
+const STREAM_REQ: Request = Request {
+    kind: PromptKind::Summary,
+    system: None,
+    prompt: "Hello world",
+    format: None,
+    deadline: None,
+};
+
+#[tokio::test]
+async fn streamed_reply_is_assembled() {
+    let server = MockServer::start().await;
+    mount_stream(&server, Duration::ZERO).await;
+    let r = streaming(server.uri(), None)
+        .summarize(STREAM_REQ)
+        .await
+        .unwrap();
+    assert_eq!(r.text, "- Sync is slow");
+    assert!(!r.truncated);
+    assert_eq!((r.prompt_tokens, r.completion_tokens), (26, 3));
+    let t = r.timings.unwrap();
+    assert_eq!(t.total, Duration::from_millis(2_500));
+    assert_eq!(t.eval, Duration::from_millis(1_500));
+}
+
+#[tokio::test]
+async fn streamed_final_line_needs_no_newline() {
+    let server = MockServer::start().await;
+    let lines = stream_lines().trim_end().to_string();
+    mount_stream_body(&server, lines, Duration::ZERO).await;
+    let r = streaming(server.uri(), None)
+        .summarize(STREAM_REQ)
+        .await
+        .unwrap();
+    assert_eq!(r.text, "- Sync is slow");
+    assert!(!r.truncated);
+    assert_eq!(r.completion_tokens, 3);
+}
+
+#[tokio::test]
+async fn streamed_reply_stops_at_token_cap() {
+    let server = MockServer::start().await;
+    mount_stream(&server, Duration::ZERO).await;
+    let r = streaming(server.uri(), Some(2))
+        .summarize(STREAM_REQ)
+        .await
+        .unwrap();
+    assert_eq!(r.text, "- Sync is");
+    assert!(r.truncated);
+    assert_eq!(r.timings, None);
+}
+
+#[tokio::test]
+async fn streamed_reply_is_cut_off_at_deadline() {
+    let server = MockServer::start().await;
+    mount_stream(&server, Duration::from_secs(5)).await;
+    let req = Request {
+        deadline: Some(tokio::time::Instant::now() + Duration::from_millis(200)),
+        ..STREAM_REQ
+    };
+    let r = streaming(server.uri(), None).summarize(req).await.unwrap();
+    assert!(r.truncated);
+    assert!(r.text.is_empty());
+}
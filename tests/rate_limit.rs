@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tokio::time::Instant;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+use zc_forum_etl::http::{RateLimit, RateLimitedClient, TokenBucket, is_status, parse_retry_after};
+
+fn limits() -> RateLimit {
+    RateLimit {
+        requests_per_sec: 100.0,
+        burst: 10,
+        max_requests_per_host: 100,
+        max_elapsed: Duration::from_secs(1),
+        max_retry_after: Duration::from_secs(60),
+    }
+}
+
+#[test]
+fn parses_retry_after_values() {
+    let now = OffsetDateTime::from_unix_timestamp(1_704_067_200).unwrap(); // 2024-01-01
+    assert_eq!(parse_retry_after("7", now), Some(Duration::from_secs(7)));
+    assert_eq!(
+        parse_retry_after("Mon, 01 Jan 2024 00:00:30 GMT", now),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(
+        parse_retry_after("Sun, 31 Dec 2023 23:59:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon", OffsetDateTime::now_utc()), None);
+}
+
+#[tokio::test]
+async fn token_bucket_spaces_requests_after_burst() {
+    let bucket = TokenBucket::new(10.0, 2);
+    let start = Instant::now();
+    assert!(bucket.reserve() <= start + Duration::from_millis(5));
+    assert!(bucket.reserve() <= start + Duration::from_millis(5));
+    let third = bucket.reserve();
+    assert!(third >= start + Duration::from_millis(90));
+}
+
+#[tokio::test]
+async fn honours_retry_after_on_429() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest.json"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/latest.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+        .mount(&server)
+        .await;
+    let client = RateLimitedClient::new(
+        Client::new(),
+        RateLimit {
+            max_elapsed: Duration::from_secs(5),
+            ..limits()
+        },
+    );
+    let url = format!("{}/latest.json", server.uri());
+    let start = Instant::now();
+    let body: Value = client.get_json(&url, &[]).await.unwrap();
+    assert_eq!(body["ok"], true);
+    assert!(start.elapsed() >= Duration::from_millis(900));
+    assert_eq!(client.requests_sent(&url), 2);
+}
+
+#[tokio::test]
+async fn retry_after_past_the_budget_gives_up() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+        .mount(&server)
+        .await;
+    let client = RateLimitedClient::new(Client::new(), limits());
+    let start = Instant::now();
+    let err = client
+        .get_json::<Value>(&format!("{}/latest.json", server.uri()), &[])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("429"), "{err}");
+    // One wait of at most the 1s budget, then no further attempt.
+    assert!(start.elapsed() < Duration::from_secs(3));
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn server_errors_are_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(502))
+        .mount(&server)
+        .await;
+    let client = RateLimitedClient::new(Client::new(), limits());
+    let res: anyhow::Result<Value> = client
+        .get_json(&format!("{}/t/1.json", server.uri()), &[])
+        .await;
+    assert!(res.is_err());
+    assert!(server.received_requests().await.unwrap().len() > 1);
+}
+
+#[tokio::test]
+async fn client_errors_fail_fast_with_status() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let client = RateLimitedClient::new(Client::new(), limits());
+    let err = client
+        .get_json::<Value>(&format!("{}/t/1.json", server.uri()), &[])
+        .await
+        .unwrap_err();
+    assert!(is_status(&err, StatusCode::NOT_FOUND));
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn request_budget_is_enforced_per_host() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .mount(&server)
+        .await;
+    let client = RateLimitedClient::new(
+        Client::new(),
+        RateLimit {
+            max_requests_per_host: 2,
+            ..limits()
+        },
+    );
+    let url = format!("{}/latest.json", server.uri());
+    for _ in 0..2 {
+        client.get_json::<Value>(&url, &[]).await.unwrap();
+    }
+    let err = client.get_json::<Value>(&url, &[]).await.unwrap_err();
+    assert!(err.to_string().contains("budget"));
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
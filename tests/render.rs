@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+
+use time::OffsetDateTime;
+use zc_forum_etl::{
+    DigestItem, Forum,
+    render::{FAILED_NOTICE, ForumDigest, render_html, render_rss},
+};
+
+fn item(title: &str) -> DigestItem {
+    DigestItem {
+        post_id: 2,
+        topic_id: 1,
+        created_at: OffsetDateTime::UNIX_EPOCH,
+        author: "alice".to_string(),
+        title: title.to_string(),
+        url: "https://example.org/t/1/2".to_string(),
+        summary: "- one\n- two".to_string(),
+        structured: None,
+    }
+}
+
+#[test]
+fn single_forum_renders_flat() {
+    let forum = Forum::default();
+    let sections = [ForumDigest {
+        forum: &forum,
+        items: vec![item("Topic")],
+        failed: HashSet::new(),
+    }];
+    let html = render_html("Digest", &sections);
+    assert!(html.contains("<h2><a href=\"https://example.org/t/1/2\">Topic</a></h2>"));
+    assert!(html.contains("<p>- one<br>- two</p>"));
+    assert!(!html.contains("<section>"));
+}
+
+#[test]
+fn merged_digest_has_section_per_forum() {
+    let zcash = Forum::default();
+    let other = Forum {
+        name: "Other Forum".to_string(),
+        base_url: "https://other.example.org".to_string(),
+        output_dir: "public/other".into(),
+        ..Forum::default()
+    };
+    let sections = [
+        ForumDigest {
+            forum: &zcash,
+            items: vec![item("A")],
+            failed: HashSet::new(),
+        },
+        ForumDigest {
+            forum: &other,
+            items: vec![item("B")],
+            failed: HashSet::new(),
+        },
+    ];
+    let html = render_html("Digest", &sections);
+    let a = html.find(">Zcash Forum</a></h2>").unwrap();
+    let b = html.find(">Other Forum</a></h2>").unwrap();
+    assert!(a < b);
+    assert!(html.contains("<h3><a href=\"https://example.org/t/1/2\">B</a></h3>"));
+
+    let rss = render_rss("Digest", &zcash.base_url, "desc", &sections);
+    assert!(rss.contains("<category>Other Forum</category>"));
+}
+
+#[test]
+fn failed_topics_render_notice() {
+    let forum = Forum::default();
+    let mut failed = item("Broken");
+    failed.summary.clear();
+    let sections = [ForumDigest {
+        forum: &forum,
+        items: vec![failed],
+        failed: HashSet::from([1]),
+    }];
+    let html = render_html("Digest", &sections);
+    assert!(html.contains(&format!("<p class=\"failed\">{FAILED_NOTICE}</p>")));
+
+    let rss = render_rss("Digest", &forum.base_url, "desc", &sections);
+    assert!(rss.contains(FAILED_NOTICE));
+}
+
+#[test]
+fn interpolated_text_is_escaped() {
+    let forum = Forum {
+        name: "R&D <Forum>".to_string(),
+        base_url: "https://example.org/?a=1&b=\"2\"".to_string(),
+        ..Forum::default()
+    };
+    let other = Forum::default();
+    let mut topic = item("<script>alert(1)</script>");
+    topic.url = "https://example.org/t/1/2\"onmouseover=\"x".to_string();
+    topic.summary = "- a < b\n- <b>bold</b>".to_string();
+    let sections = [
+        ForumDigest {
+            forum: &forum,
+            items: vec![topic],
+            failed: HashSet::new(),
+        },
+        ForumDigest {
+            forum: &other,
+            items: Vec::new(),
+            failed: HashSet::new(),
+        },
+    ];
+    let html = render_html("Digest <b>", &sections);
+    assert!(html.contains("<title>Digest &lt;b&gt;</title>"));
+    assert!(html.contains(
+        "<h2><a href=\"https://example.org/?a=1&amp;b=&quot;2&quot;\">R&amp;D &lt;Forum&gt;</a></h2>"
+    ));
+    assert!(html.contains(
+        "<a href=\"https://example.org/t/1/2&quot;onmouseover=&quot;x\">&lt;script&gt;alert(1)&lt;/script&gt;</a>"
+    ));
+    assert!(html.contains("<p>- a &lt; b<br>- &lt;b&gt;bold&lt;/b&gt;</p>"));
+    assert!(!html.contains("<script>"));
+}
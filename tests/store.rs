@@ -0,0 +1,203 @@
+use time::{Duration, OffsetDateTime};
+use zc_forum_etl::{
+    DigestItem, Post,
+    report::{Failure, Stage},
+    store::Store,
+    structured::StructuredSummary,
+};
+
+const FORUM: &str = "https://forum.zcashcommunity.com";
+
+fn post(id: u64, created_at: OffsetDateTime) -> Post {
+    Post {
+        id,
+        cooked: format!("<p>post {id}</p>"),
+        created_at,
+        username: "alice".to_string(),
+        post_number: id % 10,
+    }
+}
+
+#[test]
+fn migrations_are_idempotent() {
+    let path = std::env::temp_dir().join(format!("zc-store-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let first = Store::open(&path).unwrap().schema_version().unwrap();
+    let second = Store::open(&path).unwrap().schema_version().unwrap();
+    assert!(first > 0);
+    assert_eq!(first, second);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn topics_and_posts_filtered_by_cutoff() {
+    let mut store = Store::open_in_memory().unwrap();
+    let now = OffsetDateTime::now_utc();
+    let cutoff = now - Duration::hours(24);
+    store
+        .upsert_topic(FORUM, 1, "Old", &[post(10, now - Duration::days(3))], 1)
+        .unwrap();
+    store
+        .upsert_topic(
+            FORUM,
+            2,
+            "Fresh",
+            &[post(20, now - Duration::days(2)), post(21, now)],
+            2,
+        )
+        .unwrap();
+    // Re-fetching a topic updates it in place.
+    store
+        .upsert_topic(FORUM, 2, "Fresh (renamed)", &[post(21, now)], 2)
+        .unwrap();
+
+    let topics = store.topics_active_since(FORUM, cutoff).unwrap();
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0].id, 2);
+    assert_eq!(topics[0].title, "Fresh (renamed)");
+
+    let posts = store.posts_since(FORUM, 2, cutoff).unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].id, 21);
+    assert_eq!(posts[0].created_at, now);
+    assert!(
+        store
+            .topics_active_since("https://other.example.org", cutoff)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn high_water_mark_only_moves_forward() {
+    let mut store = Store::open_in_memory().unwrap();
+    let now = OffsetDateTime::now_utc();
+    assert_eq!(store.high_water(FORUM, 7).unwrap(), 0);
+    store
+        .upsert_topic(FORUM, 7, "Busy", &[post(71, now), post(73, now)], 3)
+        .unwrap();
+    store.upsert_topic(FORUM, 7, "Busy", &[], 2).unwrap();
+    assert_eq!(store.high_water(FORUM, 7).unwrap(), 3);
+    store
+        .upsert_topic(FORUM, 7, "Busy", &[post(75, now)], 5)
+        .unwrap();
+    assert_eq!(store.high_water(FORUM, 7).unwrap(), 5);
+
+    let known = store.known_post_ids(FORUM, 7).unwrap();
+    assert_eq!(known.len(), 3);
+    assert!(known.contains(&73));
+    let posts = store.posts_since(FORUM, 7, now).unwrap();
+    assert_eq!(posts.iter().map(|p| p.post_number).max(), Some(5));
+}
+
+#[test]
+fn private_topics_are_flagged() {
+    let mut store = Store::open_in_memory().unwrap();
+    let now = OffsetDateTime::now_utc();
+    store
+        .upsert_topic(FORUM, 1, "Members only", &[post(11, now)], 1)
+        .unwrap();
+    store
+        .upsert_topic(FORUM, 2, "Public", &[post(21, now)], 1)
+        .unwrap();
+    store.set_private(FORUM, 1, true).unwrap();
+    store.set_private(FORUM, 2, false).unwrap();
+    // Unknown topics are ignored.
+    store.set_private(FORUM, 3, true).unwrap();
+    assert_eq!(store.private_topics(FORUM).unwrap(), [1].into());
+
+    // Moving a topic out of the category clears the flag.
+    store.set_private(FORUM, 1, false).unwrap();
+    assert!(store.private_topics(FORUM).unwrap().is_empty());
+}
+
+#[test]
+fn topic_labels_are_returned_with_active_topics() {
+    let mut store = Store::open_in_memory().unwrap();
+    let now = OffsetDateTime::now_utc();
+    store
+        .upsert_topic(FORUM, 1, "Grant update", &[post(11, now)], 1)
+        .unwrap();
+    let tags = vec!["zcg".to_string(), "nu7".to_string()];
+    store
+        .set_labels(FORUM, 1, Some("grants/zcg-updates"), &tags)
+        .unwrap();
+    let topics = store
+        .topics_active_since(FORUM, now - Duration::hours(1))
+        .unwrap();
+    assert_eq!(topics[0].category.as_deref(), Some("grants/zcg-updates"));
+    assert_eq!(topics[0].tags, tags);
+}
+
+#[test]
+fn digest_items_are_kept_per_run() {
+    let mut store = Store::open_in_memory().unwrap();
+    assert!(store.latest_run("summarize").unwrap().is_none());
+
+    let run_id = store.begin_run("summarize", 24, "qwen2.5:latest").unwrap();
+    let items: Vec<DigestItem> = ["B", "A"]
+        .iter()
+        .enumerate()
+        .map(|(i, title)| DigestItem {
+            post_id: i as u64,
+            topic_id: 100 + i as u64,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            author: "bob".to_string(),
+            title: title.to_string(),
+            url: format!("{FORUM}/t/{}/{i}", 100 + i),
+            summary: "- point".to_string(),
+            structured: (i == 0).then(|| StructuredSummary {
+                headline: "Point".to_string(),
+                bullets: vec!["point".to_string()],
+                decisions: Vec::new(),
+                open_questions: vec!["when?".to_string()],
+                action_items: Vec::new(),
+            }),
+        })
+        .collect();
+    store.save_digest_items(run_id, FORUM, &items).unwrap();
+    // Unfinished runs are not rendered.
+    assert!(store.latest_run("summarize").unwrap().is_none());
+    store.finish_run(run_id, "ok").unwrap();
+
+    let run = store.latest_run("summarize").unwrap().unwrap();
+    assert_eq!(run.id, run_id);
+    assert_eq!(run.model, "qwen2.5:latest");
+    assert!(run.finished_at.is_some());
+    let stored = store.digest_items(run.id, FORUM).unwrap();
+    let titles: Vec<_> = stored.iter().map(|i| i.title.as_str()).collect();
+    assert_eq!(titles, ["B", "A"]);
+    assert_eq!(stored[0].structured, items[0].structured);
+    assert!(stored[1].structured.is_none());
+}
+
+#[test]
+fn failures_recorded_per_run() {
+    let store = Store::open_in_memory().unwrap();
+    let run = store.begin_run("summarize", 24, "qwen2.5:latest").unwrap();
+    let failure = Failure {
+        forum: FORUM.to_string(),
+        topic_id: Some(7),
+        title: Some("Broken".to_string()),
+        stage: Stage::Summarize,
+        reason: "timed out after 240s".to_string(),
+    };
+    store.record_failure(run, &failure).unwrap();
+    store.finish_run(run, "partial").unwrap();
+
+    assert_eq!(store.failures(run).unwrap(), vec![failure]);
+    // Partial runs still count as the latest finished run.
+    assert_eq!(store.latest_run("summarize").unwrap().unwrap().id, run);
+}
+
+#[test]
+fn synced_since_is_recorded_per_topic() {
+    let mut store = Store::open_in_memory().unwrap();
+    let now = OffsetDateTime::now_utc();
+    assert_eq!(store.synced_since(FORUM, 7).unwrap(), None);
+    store.set_synced_since(FORUM, 7, now).unwrap();
+    assert_eq!(store.synced_since(FORUM, 7).unwrap(), None);
+    store.upsert_topic(FORUM, 7, "Busy", &[], 3).unwrap();
+    store.set_synced_since(FORUM, 7, now).unwrap();
+    assert_eq!(store.synced_since(FORUM, 7).unwrap(), Some(now));
+}
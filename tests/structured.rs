@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use zc_forum_etl::{
+    ollama::{OllamaSummarizer, Options},
+    prompt::PromptKind,
+    render::{ForumDigest, render_html},
+    structured::{SCHEMA, StructuredSummary, summarize_structured},
+    summarizer::{Request, Summarizer, SummaryResult},
+};
+
+const VALID: &str = r#"{"headline": "Wallet sync is slow [post:12]", "bullets": ["Sync takes hours"],
+    "decisions": [], "open_questions": ["Is <b>v6</b> affected?"], "action_items": []}"#;
+
+/// Replies with the scripted texts in order and records the prompts it saw.
+struct Scripted {
+    replies: Mutex<Vec<&'static str>>,
+    prompts: Mutex<Vec<(String, bool)>>,
+}
+
+impl Summarizer for Scripted {
+    fn model(&self) -> &str {
+        "scripted"
+    }
+
+    fn summarize<'a>(&'a self, req: Request<'a>) -> BoxFuture<'a, Result<SummaryResult>> {
+        Box::pin(async move {
+            self.prompts
+                .lock()
+                .unwrap()
+                .push((req.prompt.to_string(), req.format.is_some()));
+            Ok(SummaryResult {
+                text: self.replies.lock().unwrap().remove(0).to_string(),
+                ..Default::default()
+            })
+        })
+    }
+}
+
+fn request(prompt: &str) -> Request<'_> {
+    Request {
+        kind: PromptKind::Summary,
+        system: None,
+        prompt,
+        format: None,
+        deadline: None,
+    }
+}
+
+#[test]
+fn parse_validates_and_scrubs() {
+    let s = StructuredSummary::parse(VALID).unwrap();
+    assert_eq!(s.headline, "Wallet sync is slow");
+    assert_eq!(
+        s.to_plain_text(),
+        "Wallet sync is slow\n- Sync takes hours\nOpen questions:\n- Is <b>v6</b> affected?"
+    );
+
+    assert!(StructuredSummary::parse("- just bullets").is_err());
+    assert!(StructuredSummary::parse(r#"{"headline": "x", "bullets": ["y"]}"#).is_err());
+    let empty = r#"{"headline": "x", "bullets": [], "decisions": [], "open_questions": [], "action_items": []}"#;
+    assert!(StructuredSummary::parse(empty).is_err());
+    assert_eq!(SCHEMA["required"].as_array().unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn invalid_reply_is_asked_again_once() {
+    let summarizer = Scripted {
+        replies: Mutex::new(vec!["not json", VALID]),
+        prompts: Mutex::new(Vec::new()),
+    };
+    let (s, _) = summarize_structured(&summarizer, request("Thread: sync"))
+        .await
+        .unwrap();
+    assert_eq!(s.bullets, ["Sync takes hours"]);
+    let prompts = summarizer.prompts.lock().unwrap().clone();
+    assert_eq!(prompts.len(), 2);
+    assert!(prompts.iter().all(|(_, format)| *format));
+    assert!(
+        prompts[1]
+            .0
+            .starts_with("Thread: sync\n\nYour previous reply was rejected")
+    );
+
+    let summarizer = Scripted {
+        replies: Mutex::new(vec!["not json", "{}", VALID]),
+        prompts: Mutex::new(Vec::new()),
+    };
+    assert!(
+        summarize_structured(&summarizer, request("Thread: sync"))
+            .await
+            .is_err()
+    );
+    assert_eq!(summarizer.prompts.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn structured_summary_renders_sections() {
+    let forum = zc_forum_etl::Forum::default();
+    let structured = StructuredSummary::parse(VALID).unwrap();
+    let item = zc_forum_etl::DigestItem {
+        post_id: 2,
+        topic_id: 1,
+        created_at: time::OffsetDateTime::UNIX_EPOCH,
+        author: "alice".to_string(),
+        title: "Sync".to_string(),
+        url: "https://example.org/t/1/2".to_string(),
+        summary: structured.to_plain_text(),
+        structured: Some(structured),
+    };
+    let sections = [ForumDigest {
+        forum: &forum,
+        items: vec![item],
+        failed: Default::default(),
+    }];
+    let html = render_html("Digest", &sections);
+    assert!(html.contains(
+        "<p class=\"headline\">Wallet sync is slow</p><ul><li>Sync takes hours</li></ul>"
+    ));
+    assert!(html.contains(
+        "<p class=\"label\">Open questions</p><ul><li>Is &lt;b&gt;v6&lt;/b&gt; affected?</li></ul>"
+    ));
+    assert!(!html.contains("Decisions"));
+}
+
+#[test]
+fn cut_off_json_is_salvaged() {
+    let s = StructuredSummary::salvage(
+        r#"{"headline": "Wallet sync [post:3]", "bullets": ["Sync takes hours", "Fix in v6.1 \"#,
+    )
+    .unwrap();
+    assert_eq!(s.headline, "Wallet sync");
+    assert_eq!(s.bullets, ["Sync takes hours", "Fix in v6.1"]);
+    assert!(s.decisions.is_empty());
+    // A headline cut off mid-key leaves nothing to keep.
+    assert_eq!(StructuredSummary::salvage(r#"{"head"#), None);
+    assert_eq!(StructuredSummary::salvage("no json"), None);
+}
+
+/// Ollama stand-in that streams `lines` as NDJSON and then stalls.
+async fn stalling_ollama(lines: Vec<serde_json::Value>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut sock, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0u8; 64 * 1024];
+        let _ = sock.read(&mut buf).await.unwrap();
+        let body: String = lines.iter().map(|l| format!("{l}\n")).collect();
+        let head = "HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\ntransfer-encoding: chunked\r\n\r\n";
+        let chunk = format!("{:x}\r\n{body}\r\n", body.len());
+        sock.write_all(head.as_bytes()).await.unwrap();
+        sock.write_all(chunk.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn streamed_structured_reply_cut_off_at_deadline_is_kept() {
+    let parts = [
+        r#"{"headline": "Sync is slow","#,
+        r#" "bullets": ["Takes hours"#,
+    ];
+    let lines = parts
+        .iter()
+        .map(|t| serde_json::json!({"message": {"role": "assistant", "content": t}, "done": false}))
+        .collect();
+    let ollama = OllamaSummarizer {
+        client: Client::new(),
+        base_url: stalling_ollama(lines).await,
+        model: "test-model".to_string(),
+        options: Options::default(),
+        prompt_options: HashMap::new(),
+        stream: true,
+        max_output_tokens: None,
+        max_elapsed: Duration::from_secs(1),
+    };
+    let req = Request {
+        deadline: Some(tokio::time::Instant::now() + Duration::from_millis(1500)),
+        ..request("Thread: t")
+    };
+    let (s, r) = summarize_structured(&ollama, req).await.unwrap();
+    assert!(r.truncated);
+    assert_eq!(s.headline, "Sync is slow");
+    assert_eq!(s.bullets, ["Takes hours"]);
+}
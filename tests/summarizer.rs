@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde_json::json;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{body_partial_json, header, method, path},
+};
+use zc_forum_etl::{
+    config::{Backend, Config},
+    openai::OpenAiSummarizer,
+    prompt::PromptKind,
+    structured::SCHEMA,
+    summarizer::{self, MockSummarizer, Request, Summarizer, SummaryResult, Timings, Usage},
+};
+
+const REQ: Request = Request {
+    kind: PromptKind::Summary,
+    system: Some("Be brief."),
+    prompt: "Thread: test\n\nContent excerpt:\n---\nHello world\n---",
+    format: None,
+    deadline: None,
+};
+
+fn openai(base_url: String) -> OpenAiSummarizer {
+    OpenAiSummarizer {
+        client: Client::new(),
+        base_url,
+        model: "qwen2.5-7b-instruct".to_string(),
+        api_key: Some("secret".to_string()),
+        max_elapsed: Duration::from_secs(1),
+    }
+}
+
+#[tokio::test]
+async fn mock_is_deterministic() {
+    let a = MockSummarizer.summarize(REQ).await.unwrap();
+    let b = MockSummarizer.summarize(REQ).await.unwrap();
+    assert_eq!(a, b);
+    assert_eq!(a.text, "- Thread: test");
+    assert!(a.prompt_tokens > 0);
+}
+
+#[tokio::test]
+async fn openai_backend_reads_choice_and_usage() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", "Bearer secret"))
+        .and(body_partial_json(
+            json!({"model": "qwen2.5-7b-instruct", "stream": false}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "- hello"}}],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}
+        })))
+        .mount(&server)
+        .await;
+    let r = openai(server.uri()).summarize(REQ).await.unwrap();
+    assert_eq!(r.text, "- hello");
+    assert_eq!((r.prompt_tokens, r.completion_tokens), (12, 3));
+}
+
+#[tokio::test]
+async fn openai_strict_schema_leaves_out_length_limits() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "{}"}}]
+        })))
+        .mount(&server)
+        .await;
+    let req = Request {
+        format: Some(&SCHEMA),
+        ..REQ
+    };
+    openai(server.uri()).summarize(req).await.unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    let body: serde_json::Value = requests[0].body_json().unwrap();
+    let format = &body["response_format"]["json_schema"];
+    assert_eq!(format["strict"], true);
+    let schema = format["schema"].to_string();
+    assert!(
+        !schema.contains("minLength") && !schema.contains("minItems"),
+        "{schema}"
+    );
+    assert_eq!(format["schema"]["required"], SCHEMA["required"]);
+    assert_eq!(format["schema"]["additionalProperties"], false);
+    assert_eq!(
+        format["schema"]["properties"]["bullets"]["items"]["type"],
+        "string"
+    );
+}
+
+#[tokio::test]
+async fn openai_client_error_is_permanent() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    assert!(openai(server.uri()).summarize(REQ).await.is_err());
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[test]
+fn backend_selected_by_config() {
+    let mut config = Config::from_toml("[summarize]\nbackend = \"openai\"\n").unwrap();
+    assert_eq!(config.summarize.backend, Backend::OpenAi);
+    config.set_model("llama-3.1-8b".to_string());
+    assert_eq!(config.openai.model, "llama-3.1-8b");
+    assert_eq!(
+        summarizer::from_config(&config, Client::new()).model(),
+        "llama-3.1-8b"
+    );
+
+    config
+        .apply_overrides(|k| (k == "LLM_BACKEND").then(|| "mock".to_string()))
+        .unwrap();
+    assert_eq!(config.model(), "mock");
+}
+
+#[test]
+fn usage_aggregates_tokens_per_second() {
+    let timed = SummaryResult {
+        prompt_tokens: 100,
+        completion_tokens: 40,
+        timings: Some(Timings {
+            total: Duration::from_secs(5),
+            load: Duration::from_secs(1),
+            eval: Duration::from_secs(2),
+        }),
+        ..Default::default()
+    };
+    let estimated = SummaryResult {
+        prompt_tokens: 10,
+        completion_tokens: 5,
+        ..Default::default()
+    };
+    let mut usage = Usage::default();
+    usage.add(&timed);
+    usage.add(&estimated);
+    let mut run = Usage::default();
+    run.merge(&usage);
+    assert_eq!(run.calls, 2);
+    assert_eq!((run.prompt_tokens, run.completion_tokens), (110, 45));
+    assert_eq!(run.total_secs, 5.0);
+    // Only calls with timings count towards the rate.
+    assert_eq!(run.tokens_per_sec, Some(20.0));
+}
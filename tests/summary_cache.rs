@@ -0,0 +1,68 @@
+use zc_forum_etl::{
+    PROMPT_TEMPLATE, build_prompt, prompt::SYSTEM_PROMPT, store::Store, summary_cache_key,
+};
+
+#[test]
+fn cache_key_depends_on_every_part() {
+    let key = summary_cache_key("qwen2.5:latest", None, PROMPT_TEMPLATE, "chunk");
+    assert_eq!(key.len(), 64);
+    assert_eq!(
+        key,
+        summary_cache_key("qwen2.5:latest", None, PROMPT_TEMPLATE, "chunk")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("zc-forum-summarizer", None, PROMPT_TEMPLATE, "chunk")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("qwen2.5:latest", None, "other", "chunk")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("qwen2.5:latest", None, PROMPT_TEMPLATE, "chunk2")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key(
+            "qwen2.5:latest",
+            Some(SYSTEM_PROMPT),
+            PROMPT_TEMPLATE,
+            "chunk"
+        )
+    );
+    assert_ne!(
+        summary_cache_key("qwen2.5:latest", Some(""), PROMPT_TEMPLATE, "chunk"),
+        key
+    );
+    // Part boundaries are unambiguous.
+    assert_ne!(
+        summary_cache_key("ab", None, "c", "d"),
+        summary_cache_key("a", None, "bc", "d")
+    );
+}
+
+#[test]
+fn build_prompt_fills_template() {
+    assert_eq!(
+        build_prompt("Title {body}", "text"),
+        "Thread: Title {body}\n\nContent excerpt:\n---\ntext\n---"
+    );
+}
+
+#[test]
+fn cache_invalidates_by_model() {
+    let store = Store::open_in_memory().unwrap();
+    let a = summary_cache_key("model-a", None, PROMPT_TEMPLATE, "chunk");
+    let b = summary_cache_key("model-b", None, PROMPT_TEMPLATE, "chunk");
+    store.cache_summary(&a, "model-a", "- a").unwrap();
+    store.cache_summary(&b, "model-b", "- b").unwrap();
+    assert_eq!(store.cached_summary(&a).unwrap().as_deref(), Some("- a"));
+
+    assert_eq!(store.invalidate_summaries(Some("model-a")).unwrap(), 1);
+    assert!(store.cached_summary(&a).unwrap().is_none());
+    assert_eq!(store.cached_summary(&b).unwrap().as_deref(), Some("- b"));
+
+    assert_eq!(store.invalidate_summaries(None).unwrap(), 1);
+    assert!(store.cached_summary(&b).unwrap().is_none());
+}